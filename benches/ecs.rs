@@ -0,0 +1,143 @@
+/* Timings of the hot World paths with 100k entities, run with `cargo bench`. */
+use std::{hint::black_box, time::{Duration, Instant}};
+
+use rust_game::entities::{
+    components::{collider::Collider, motion, position},
+    systems::{
+        collision::{add_collisions, detect_collisions},
+        movement::{apply_motion, Timestep},
+        schedule::{Schedule, Stage},
+        tile_collision::collide_with_tiles,
+    },
+    EntityId, World,
+};
+
+const ENTITIES: usize = 100_000;
+const RUNS: usize = 10;
+const BODIES: usize = 5_000;
+const COLLISION_TICKS: usize = 10;
+
+#[derive(Clone, Copy)]
+struct Position(f32, f32);
+#[derive(Clone, Copy)]
+struct Velocity(f32, f32);
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+struct Health(u32);
+#[derive(Clone, Copy)]
+struct Frozen;
+
+fn main() {
+    bench("spawn bundle", |_| {
+        let mut world = World::init();
+        for i in 0..ENTITIES {
+            world.spawn((Position(i as f32, 0.0), Velocity(1.0, 1.0)));
+        }
+        world
+    });
+    bench("spawn then set_component x2", |_| {
+        let mut world = World::init();
+        for i in 0..ENTITIES {
+            let id = world.new_entity();
+            world.set_component(id, Position(i as f32, 0.0)).unwrap();
+            world.set_component(id, Velocity(1.0, 1.0)).unwrap();
+        }
+        world
+    });
+    bench_on("set_component migration", populated, |world, ids| {
+        for id in ids {
+            world.set_component(*id, Health(10)).unwrap();
+        }
+    });
+    bench_on("remove_component migration", populated, |world, ids| {
+        for id in ids {
+            world.remove_component::<Velocity>(*id).unwrap();
+        }
+    });
+    bench_on("sparse marker toggle", |ids| {
+        let (mut world, ids) = populated(ids);
+        world.register_sparse::<Frozen>().unwrap();
+        (world, ids)
+    }, |world, ids| {
+        for id in ids {
+            world.set_component(*id, Frozen).unwrap();
+        }
+        for id in ids {
+            world.remove_component::<Frozen>(*id).unwrap();
+        }
+    });
+    bench_on("get by id", populated, |world, ids| {
+        let mut sum = 0.0;
+        for id in ids {
+            sum += world.get::<Position>(*id).unwrap().unwrap().0;
+        }
+        black_box(sum);
+    });
+    bench_on("query iter_mut", populated, |world, _| {
+        for (_, mut position, velocity) in world.query::<(&mut Position, &Velocity)>().iter_mut() {
+            position.0 += velocity.0;
+            position.1 += velocity.1;
+        }
+    });
+    bench_on("despawn", populated, |world, ids| {
+        for id in ids {
+            world.despawn(*id).unwrap();
+        }
+    });
+    bench_on("detect_collisions x10 ticks", bodies, |(world, schedule), _| {
+        for _ in 0..COLLISION_TICKS {
+            world.run_schedule(schedule);
+        }
+    });
+}
+
+/* Boxes and circles a few pixels apart on a 640x360 screen, all moving in different directions. */
+fn bodies(_: ()) -> ((World, Schedule), ()) {
+    let mut world = World::init();
+    world.insert_resource(Timestep(1.0 / 60.0));
+    add_collisions(&mut world);
+    for i in 0..BODIES {
+        let start = ((i % 100) as f32 * 6.4, (i / 100) as f32 * 7.2);
+        let heading = ((i % 7) as f32 * 30.0 - 90.0, (i % 11) as f32 * 20.0 - 100.0);
+        let collider = if i % 2 == 0 { Collider::new(8.0, 8.0) } else { Collider::circle(4.0) };
+        world.spawn((position::Position(start.into()), motion::Velocity(heading.into()), collider.with_layers(1 << (i % 3), 0b011)));
+    }
+    let mut schedule = Schedule::new();
+    schedule.add_system(Stage::Physics, "apply_motion", apply_motion());
+    // without a Map the colliders just move
+    schedule.add_system(Stage::Physics, "collide_with_tiles", collide_with_tiles()).after("apply_motion");
+    schedule.add_system(Stage::Physics, "detect_collisions", detect_collisions()).after("collide_with_tiles");
+    ((world, schedule), ())
+}
+
+fn populated(_: ()) -> (World, Vec<EntityId>) {
+    let mut world = World::init();
+    let ids = (0..ENTITIES).map(|i| world.spawn((Position(i as f32, 0.0), Velocity(1.0, 1.0)))).collect();
+    (world, ids)
+}
+
+fn bench<R>(name: &str, mut run: impl FnMut(()) -> R) {
+    bench_on(name, |_| ((), ()), |_, _| {
+        black_box(run(()));
+    });
+}
+
+/* Setup is left out of the timing, only run is measured. Prints the fastest and the median run. */
+fn bench_on<W, I>(name: &str, mut setup: impl FnMut(()) -> (W, I), mut run: impl FnMut(&mut W, &I)) {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let (mut world, input) = setup(());
+            let start = Instant::now();
+            run(&mut world, &input);
+            let elapsed = start.elapsed();
+            drop(black_box(world));
+            elapsed
+        })
+        .collect();
+    times.sort();
+    println!("{:<32} min {:>9.3} ms   median {:>9.3} ms", name, ms(times[0]), ms(times[RUNS / 2]));
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
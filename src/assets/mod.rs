@@ -4,22 +4,27 @@ use sdl2::render::{Texture, TextureCreator};
 use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use self::texture_atlas::TextureAtlas;
+pub mod prefab;
 pub mod texture_atlas;
 pub mod texture_region;
 
 const ASSETS_LOCATION: &str = "assets/";
 const TEXTURE_LOCATION: &str = concatcp!(ASSETS_LOCATION, "textures/");
+const PREFAB_LOCATION: &str = concatcp!(ASSETS_LOCATION, "prefabs/");
 
+/* Loads each resource once and hands out shared handles to it. Handles are Arcs so a manager of
+ * thread safe resources, like the PrefabManager, can be stored in the World.
+ */
 pub struct ResourceManager<'asset, K, R, L>
 where
     K: Hash + Eq,
     L: ResourceLoader<'asset, R>,
 {
     loader: &'asset L,
-    cache: HashMap<K, Rc<R>>,
+    cache: HashMap<K, Arc<R>>,
 }
 
 impl<'asset, K, R, L> ResourceManager<'asset, K, R, L>
@@ -34,7 +39,7 @@ where
         }
     }
 
-    pub fn load<D>(&mut self, details: &D) -> Result<Rc<R>, String>
+    pub fn load<D>(&mut self, details: &D) -> Result<Arc<R>, String>
     where
         L: ResourceLoader<'asset, R, Args = D>,
         D: Eq + Hash + ?Sized,
@@ -43,7 +48,7 @@ where
         if let Some(resource) = self.cache.get(details).cloned() {
             return Ok(resource);
         }
-        let resource = Rc::new(self.loader.load(details)?);
+        let resource = Arc::new(self.loader.load(details)?);
         self.cache.insert(details.into(), resource.clone());
         Ok(resource)
     }
@@ -0,0 +1,66 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{fs::File, io::BufReader};
+
+use super::{ResourceLoader, ResourceManager, PREFAB_LOCATION};
+
+/* Components an entity is spawned with, keyed by the name they were registered under in the World.
+ * Read from assets/prefabs/<name>.json, e.g. { "components": { "position": [1.0, 4.0] } }
+ */
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Prefab {
+    components: Map<String, Value>,
+}
+
+impl Prefab {
+    pub fn components(&self) -> &Map<String, Value> {
+        &self.components
+    }
+    /* The prefab's components with overrides merged in. Objects are merged key by key, anything else is replaced,
+     * so { "health": { "max": 20 } } only changes the max health. Overridden components the prefab lacks are added.
+     */
+    pub fn with_overrides(&self, overrides: &Map<String, Value>) -> Map<String, Value> {
+        let mut components = self.components.clone();
+        for (name, value) in overrides {
+            match components.get_mut(name) {
+                Some(component) => merge(component, value),
+                None => {
+                    components.insert(name.clone(), value.clone());
+                }
+            }
+        }
+        components
+    }
+}
+
+fn merge(target: &mut Value, overrides: &Value) {
+    match (target, overrides) {
+        (Value::Object(target), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match target.get_mut(key) {
+                    Some(field) => merge(field, value),
+                    None => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, value) => *target = value.clone(),
+    }
+}
+
+pub struct PrefabLoader;
+
+impl ResourceLoader<'_, Prefab> for PrefabLoader {
+    type Args = str;
+
+    fn load(&'_ self, data: &Self::Args) -> Result<Prefab, String> {
+        serde_json::from_reader(BufReader::new(
+            File::open(PREFAB_LOCATION.to_owned() + data + ".json").map_err(|e| format!("prefab {}: {}", data, e))?,
+        ))
+        .map_err(|e| format!("prefab {}: {}", data, e))
+    }
+}
+
+/* Lives in the World as a resource, World::spawn_prefab loads through it. */
+pub type PrefabManager = ResourceManager<'static, String, Prefab, PrefabLoader>;
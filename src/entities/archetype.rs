@@ -1 +1,140 @@
-type ArchetypeId = u32;
\ No newline at end of file
+use std::{collections::HashMap, mem::replace};
+
+use any_vec::AnyVec;
+
+use super::{components::{Component, ComponentTypeId}, EntityId};
+
+/* Dense index of an archetype, handed out in creation order by the World. */
+pub type ArchetypeId = u32;
+
+/* Every entity with exactly the same set of components lives in the same archetype, one row each.
+ * The signature is that set sorted, it is what identifies the archetype.
+ */
+pub struct Archetype {
+    pub(crate) components: HashMap<ComponentTypeId, Column>,
+    pub(crate) entity_ids: Vec<EntityId>,
+    pub(crate) signature: Vec<ComponentTypeId>,
+    pub(crate) edges: ArchetypeEdges,
+}
+
+/* Archetypes reached by adding or removing a single component, filled in the first time a transition happens. */
+#[derive(Default)]
+pub struct ArchetypeEdges {
+    pub(crate) add: HashMap<ComponentTypeId, ArchetypeId>,
+    pub(crate) remove: HashMap<ComponentTypeId, ArchetypeId>,
+}
+
+/* Tick of the World when a component was added and when it was last written. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ComponentTicks {
+    pub added: u32,
+    pub changed: u32,
+}
+
+impl ComponentTicks {
+    pub fn new(tick: u32) -> Self {
+        ComponentTicks { added: tick, changed: tick }
+    }
+    pub fn is_added(&self, last_run: u32) -> bool {
+        self.added > last_run
+    }
+    pub fn is_changed(&self, last_run: u32) -> bool {
+        self.changed > last_run
+    }
+}
+
+pub struct Column {
+    pub(crate) data: AnyVec<dyn Send + Sync>,
+    pub(crate) ticks: Vec<ComponentTicks>,
+}
+
+impl Column {
+    pub fn new<T: Component>() -> Self {
+        Column {
+            data: AnyVec::new::<T>(),
+            ticks: Vec::new(),
+        }
+    }
+    pub fn clone_empty(&self) -> Self {
+        Column {
+            data: self.data.clone_empty(),
+            ticks: Vec::new(),
+        }
+    }
+    /* Moves a row onto the end of another column of the same type, keeping its ticks. */
+    pub fn move_row(&mut self, row_index: usize, other: &mut Column) {
+        other.data.push(self.data.swap_remove(row_index));
+        other.ticks.push(self.ticks.swap_remove(row_index));
+    }
+    pub fn swap_remove(&mut self, row_index: usize) {
+        self.data.swap_remove(row_index);
+        self.ticks.swap_remove(row_index);
+    }
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.ticks.clear();
+    }
+}
+
+impl Archetype {
+    /* The signature must be sorted and hold exactly the keys of components. */
+    pub fn new(signature: Vec<ComponentTypeId>, components: HashMap<ComponentTypeId, Column>) -> Self {
+        debug_assert!(signature.windows(2).all(|pair| pair[0] < pair[1]), "archetype signature is not sorted");
+        debug_assert!(signature.len() == components.len() && signature.iter().all(|name| components.contains_key(name)));
+        Archetype {
+            components,
+            entity_ids: Vec::new(),
+            signature,
+            edges: ArchetypeEdges::default(),
+        }
+    }
+
+    pub fn signature(&self) -> &[ComponentTypeId] {
+        &self.signature
+    }
+
+    /* Caller must remove the entity pointers. */
+    pub fn clear(&mut self) {
+        self.entity_ids.clear();
+        for storage in self.components.values_mut() {
+            storage.clear();
+        }
+    }
+
+    pub fn new_row(&mut self, entity_id: EntityId) -> usize {
+        let new_row_index = self.entity_ids.len();
+        self.entity_ids.push(entity_id);
+        new_row_index
+    }
+
+    /* Caller must fix entity pointers. Returned id is the entity id of the swapped entity.
+     * New row for the swapped entity is the row_index
+     */
+    pub fn swap_remove(&mut self, row_index: usize) -> EntityId {
+        let swapped = *self.entity_ids.last().unwrap();
+        self.entity_ids.swap_remove(row_index);
+        for storage in self.components.values_mut() {
+            storage.swap_remove(row_index);
+        }
+        swapped
+    }
+    pub fn set<T: Component>(&mut self, row_index: usize, component: T, tick: u32) -> Result<T, String> {
+        let column = self.components.get_mut(&ComponentTypeId::of::<T>()).ok_or("Set called with wrong component type for this archetype")?;
+        column.ticks.get_mut(row_index).ok_or("Row index out of bounds")?.changed = tick;
+        let target_pointer: &mut T = column.data
+        .get_mut(row_index).ok_or("Row index out of bounds")?.downcast_mut().ok_or(format!("AnyVec in wrong row, should be of type {:?}", ComponentTypeId::of::<T>()))?;
+
+        let old_val = replace(target_pointer, component);
+
+        Ok(old_val)
+    }
+
+    pub fn push<T: Component>(&mut self, component: T, tick: u32) -> Result<(), String> {
+        let column = self.components.get_mut(&ComponentTypeId::of::<T>()).ok_or("Set called with wrong component type for this archetype")?;
+        column.ticks.push(ComponentTicks::new(tick));
+        column.data
+        .downcast_mut().ok_or(format!("AnyVec in wrong row, should be of type {:?}", ComponentTypeId::of::<T>()))?.push(component);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use super::{cell::SyncCell, components::{Component, ComponentTypeId}, sparse_set::SparseSet, Archetype, Column, EntityId};
+
+/* A set of components spawned together, so the entity lands in its final archetype in one step.
+ * Implemented for tuples of components, () being an entity with no components.
+ */
+pub trait Bundle: Send + 'static {
+    fn component_ids() -> Vec<ComponentTypeId>;
+    fn columns() -> HashMap<ComponentTypeId, Column>;
+    fn push<S: BundleSink>(self, sink: &mut S);
+}
+
+/* Receives the components of a bundle one by one as it is spawned. */
+pub trait BundleSink {
+    fn push<T: Component>(&mut self, component: T);
+}
+
+/* Puts each component in its column of the archetype, or in its sparse set for the sparse ones.
+ * Caller must have added the row for the entity already.
+ */
+pub(super) struct SpawnSink<'a> {
+    pub(super) archetype: &'a mut Archetype,
+    pub(super) sparse_sets: &'a mut HashMap<ComponentTypeId, SyncCell<SparseSet>>,
+    pub(super) id: EntityId,
+    pub(super) tick: u32,
+}
+
+impl BundleSink for SpawnSink<'_> {
+    fn push<T: Component>(&mut self, component: T) {
+        match self.sparse_sets.get_mut(&ComponentTypeId::of::<T>()) {
+            Some(set) => set.get_mut().insert(self.id, component, self.tick).map(|_| ()),
+            None => self.archetype.push(component, self.tick),
+        }
+        .expect("bundle pushed into an archetype without its components");
+    }
+}
+
+macro_rules! bundle_impls {
+    ($( $name:ident )*) => {
+        #[allow(non_snake_case, unused_variables, unused_mut)]
+        impl<$($name: Component),*> Bundle for ($($name,)*)
+        {
+            fn component_ids() -> Vec<ComponentTypeId> {
+                vec![$(ComponentTypeId::of::<$name>()),*]
+            }
+            fn columns() -> HashMap<ComponentTypeId, Column> {
+                let mut columns = HashMap::new();
+                $(columns.insert(ComponentTypeId::of::<$name>(), Column::new::<$name>());)*
+                columns
+            }
+            fn push<S: BundleSink>(self, sink: &mut S) {
+                let ($($name,)*) = self;
+                $(sink.push($name);)*
+            }
+        }
+    };
+}
+
+bundle_impls! {}
+bundle_impls! { A }
+bundle_impls! { A B }
+bundle_impls! { A B C }
+bundle_impls! { A B C D }
+bundle_impls! { A B C D E }
+bundle_impls! { A B C D E F }
+bundle_impls! { A B C D E F G }
+bundle_impls! { A B C D E F G H }
+bundle_impls! { A B C D E F G H I }
+bundle_impls! { A B C D E F G H I J }
+bundle_impls! { A B C D E F G H I J K }
+bundle_impls! { A B C D E F G H I J K L }
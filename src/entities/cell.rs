@@ -0,0 +1,35 @@
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+/* A RefCell that can be shared between the threads running systems.
+ * Borrowing never blocks, a borrow that overlaps a mutable one panics just like with RefCell,
+ * so two systems running at once that forgot to declare conflicting access fail loudly instead of stalling.
+ */
+pub struct SyncCell<T> {
+    lock: RwLock<T>,
+}
+
+impl<T> SyncCell<T> {
+    pub fn new(value: T) -> Self {
+        SyncCell { lock: RwLock::new(value) }
+    }
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        match self.lock.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => panic!("already mutably borrowed"),
+        }
+    }
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        match self.lock.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => panic!("already borrowed"),
+        }
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        self.lock.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+    pub fn into_inner(self) -> T {
+        self.lock.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,64 @@
+use super::{bundle::Bundle, components::Component, EntityId, World};
+
+type Command = Box<dyn FnOnce(&mut World) + Send>;
+
+/* Structural changes recorded while the World is shared, applied in the order they were recorded.
+ * Commands targeting an entity that no longer exists by the time they are applied do nothing.
+ */
+#[derive(Default)]
+pub struct CommandQueue {
+    commands: Vec<Command>,
+}
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        CommandQueue::default()
+    }
+    pub fn push(&mut self, command: impl FnOnce(&mut World) + Send + 'static) {
+        self.commands.push(Box::new(command));
+    }
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+    pub fn apply(&mut self, world: &mut World) {
+        for command in self.commands.drain(..) {
+            command(world);
+        }
+    }
+}
+
+pub struct Commands<'w, 's> {
+    world: &'w World,
+    queue: &'s mut CommandQueue,
+}
+
+impl<'w, 's> Commands<'w, 's> {
+    pub fn new(world: &'w World, queue: &'s mut CommandQueue) -> Self {
+        Commands { world, queue }
+    }
+
+    /* The returned id can be used by later commands straight away. */
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> EntityId {
+        let id = self.world.reserve_entity();
+        self.queue.push(move |world: &mut World| world.spawn_reserved(id, bundle));
+        id
+    }
+    pub fn insert<T: Component>(&mut self, id: EntityId, component: T) {
+        self.queue.push(move |world: &mut World| {
+            world.set_component(id, component).ok();
+        });
+    }
+    pub fn remove<T: Component>(&mut self, id: EntityId) {
+        self.queue.push(move |world: &mut World| {
+            world.remove_component::<T>(id).ok();
+        });
+    }
+    pub fn despawn(&mut self, id: EntityId) {
+        self.queue.push(move |world: &mut World| {
+            world.despawn(id).ok();
+        });
+    }
+    pub fn add(&mut self, command: impl FnOnce(&mut World) + Send + 'static) {
+        self.queue.push(command);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{RwLockReadGuard, RwLockWriteGuard},
+};
+
+use super::{components::{Component, ComponentTypeId}, error::EntityError, sparse_set::SparseSet, Archetype, ComponentTicks, EntityId, World};
+
+/* Only held so the storage stays borrowed for as long as the reference lives. */
+#[allow(dead_code)]
+enum ReadGuard<'w> {
+    Archetype(RwLockReadGuard<'w, Archetype>),
+    Sparse(RwLockReadGuard<'w, SparseSet>),
+}
+
+#[allow(dead_code)]
+enum WriteGuard<'w> {
+    Archetype(RwLockWriteGuard<'w, Archetype>),
+    Sparse(RwLockWriteGuard<'w, SparseSet>),
+}
+
+/* Shared borrow of one component of one entity, from World::get.
+ * Holds a borrow of the archetype or sparse set the component lives in until it is dropped,
+ * so a mutable query over the same archetype panics while it is alive.
+ */
+pub struct Ref<'w, T: Component> {
+    _guard: ReadGuard<'w>,
+    value: *const T,
+    ticks: ComponentTicks,
+    marker: PhantomData<&'w T>,
+}
+
+impl<'w, T: Component> Ref<'w, T> {
+    pub(super) fn archetype(guard: RwLockReadGuard<'w, Archetype>, row: usize) -> Option<Self> {
+        let column = guard.components.get(&ComponentTypeId::of::<T>())?;
+        let value = column.data.downcast_ref::<T>()?.get(row)? as *const T;
+        let ticks = column.ticks[row];
+        Some(Ref {
+            _guard: ReadGuard::Archetype(guard),
+            value,
+            ticks,
+            marker: PhantomData,
+        })
+    }
+    pub(super) fn sparse(guard: RwLockReadGuard<'w, SparseSet>, id: EntityId) -> Option<Self> {
+        let value = guard.get::<T>(id)? as *const T;
+        let ticks = *guard.ticks(id)?;
+        Some(Ref {
+            _guard: ReadGuard::Sparse(guard),
+            value,
+            ticks,
+            marker: PhantomData,
+        })
+    }
+    pub fn ticks(&self) -> ComponentTicks {
+        self.ticks
+    }
+}
+
+impl<'w, T: Component> Deref for Ref<'w, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // the guard keeps the storage the pointer points into borrowed and in place
+        unsafe { &*self.value }
+    }
+}
+
+/* Exclusive borrow of one component of one entity, from World::get_mut.
+ * Like Mut, only dereferencing it mutably marks the component as changed.
+ */
+pub struct RefMut<'w, T: Component> {
+    _guard: WriteGuard<'w>,
+    value: *mut T,
+    ticks: *mut ComponentTicks,
+    this_run: u32,
+    marker: PhantomData<&'w mut T>,
+}
+
+impl<'w, T: Component> RefMut<'w, T> {
+    pub(super) fn archetype(mut guard: RwLockWriteGuard<'w, Archetype>, row: usize, this_run: u32) -> Option<Self> {
+        let column = guard.components.get_mut(&ComponentTypeId::of::<T>())?;
+        let value = column.data.downcast_mut::<T>()?.as_mut_slice().get_mut(row)? as *mut T;
+        let ticks = &mut column.ticks[row] as *mut ComponentTicks;
+        Some(RefMut {
+            _guard: WriteGuard::Archetype(guard),
+            value,
+            ticks,
+            this_run,
+            marker: PhantomData,
+        })
+    }
+    pub(super) fn sparse(mut guard: RwLockWriteGuard<'w, SparseSet>, id: EntityId, this_run: u32) -> Option<Self> {
+        let row = guard.row(id)?;
+        let value = guard.column.data.downcast_mut::<T>()?.as_mut_slice().get_mut(row)? as *mut T;
+        let ticks = &mut guard.column.ticks[row] as *mut ComponentTicks;
+        Some(RefMut {
+            _guard: WriteGuard::Sparse(guard),
+            value,
+            ticks,
+            this_run,
+            marker: PhantomData,
+        })
+    }
+    pub fn ticks(&self) -> ComponentTicks {
+        unsafe { *self.ticks }
+    }
+    /* Writes through the returned reference are not detected by Changed<T>. */
+    pub fn bypass_change_detection(&mut self) -> &mut T {
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<'w, T: Component> Deref for RefMut<'w, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.value }
+    }
+}
+
+impl<'w, T: Component> DerefMut for RefMut<'w, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {
+            (*self.ticks).changed = self.this_run;
+            &mut *self.value
+        }
+    }
+}
+
+/* Tuples of components World::get_many borrows together. */
+pub trait ComponentSet {
+    type Refs<'w>;
+
+    fn get(world: &World, id: EntityId) -> Result<Option<Self::Refs<'_>>, EntityError>;
+}
+
+macro_rules! component_set_impls {
+    ($( $name:ident )+) => {
+        impl<$($name: Component),+> ComponentSet for ($($name,)+)
+        {
+            type Refs<'w> = ($(Ref<'w, $name>,)+);
+
+            fn get(world: &World, id: EntityId) -> Result<Option<Self::Refs<'_>>, EntityError> {
+                Ok(Some(($(
+                    match world.get::<$name>(id)? {
+                        Some(component) => component,
+                        None => return Ok(None),
+                    },
+                )+)))
+            }
+        }
+    };
+}
+
+component_set_impls! { A }
+component_set_impls! { A B }
+component_set_impls! { A B C }
+component_set_impls! { A B C D }
+component_set_impls! { A B C D E }
+component_set_impls! { A B C D E F }
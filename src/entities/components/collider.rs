@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+
+use crate::maths::vector::Vector;
+
+/* Area an entity takes up, in pixels. The bounding box of the shape starts offset away from the Position.
+ * Tiles only ever see the bounding box, other colliders see the actual shape.
+ * apply_motion moves them like any other entity, collide_with_tiles after it keeps them out of solid tiles.
+ * Written in prefabs as {"size": [w, h]} or {"radius": r}, plus optional "offset": [x, y], "layers" and "mask".
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Collider {
+    #[serde(flatten)]
+    pub shape: Shape,
+    #[serde(default)]
+    pub offset: (f32, f32),
+    /* Bits of the layers this collider is on. */
+    #[serde(default = "default_layers")]
+    pub layers: u32,
+    /* Bits of the layers this collider collides with. Two colliders only collide when each one's mask has a layer of the other. */
+    #[serde(default = "default_mask")]
+    pub mask: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Shape {
+    Box { size: (f32, f32) },
+    Circle { radius: f32 },
+}
+
+fn default_layers() -> u32 {
+    1
+}
+
+fn default_mask() -> u32 {
+    u32::MAX
+}
+
+impl Collider {
+    pub fn new(width: f32, height: f32) -> Self {
+        Collider::with_shape(Shape::Box { size: (width, height) })
+    }
+    pub fn circle(radius: f32) -> Self {
+        Collider::with_shape(Shape::Circle { radius })
+    }
+    fn with_shape(shape: Shape) -> Self {
+        Collider {
+            shape,
+            offset: (0.0, 0.0),
+            layers: default_layers(),
+            mask: default_mask(),
+        }
+    }
+    pub fn with_offset(mut self, x: f32, y: f32) -> Self {
+        self.offset = (x, y);
+        self
+    }
+    pub fn with_layers(mut self, layers: u32, mask: u32) -> Self {
+        self.layers = layers;
+        self.mask = mask;
+        self
+    }
+
+    /* Top left and bottom right corners of the bounding box for an entity at position. */
+    pub fn bounds(&self, position: Vector) -> (Vector, Vector) {
+        let (width, height) = match self.shape {
+            Shape::Box { size } => size,
+            Shape::Circle { radius } => (radius * 2.0, radius * 2.0),
+        };
+        let min = Vector::new(position.x + self.offset.0, position.y + self.offset.1, 0.0);
+        let max = Vector::new(min.x + width, min.y + height, 0.0);
+        (min, max)
+    }
+
+    pub fn interacts_with(&self, other: &Collider) -> bool {
+        self.mask & other.layers != 0 && other.mask & self.layers != 0
+    }
+}
+
+/* Sides of its Collider an entity ran into a solid tile with during the last tick, set by collide_with_tiles. */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contacts {
+    pub left: bool,
+    pub right: bool,
+    pub top: bool,
+    pub bottom: bool,
+}
+
+impl Contacts {
+    pub fn any(&self) -> bool {
+        self.left || self.right || self.top || self.bottom
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entities::EntityId;
+
+/* Set through World::set_parent, which keeps the parent's Children in step. Removing it keeps them in step too. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Parent(pub(crate) EntityId);
+
+impl Parent {
+    pub fn get(&self) -> EntityId {
+        self.0
+    }
+}
+
+/* Despawning an entity despawns everything listed here too. */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Children(pub(crate) Vec<EntityId>);
+
+impl Children {
+    pub fn iter(&self) -> impl Iterator<Item = &EntityId> {
+        self.0.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
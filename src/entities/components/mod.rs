@@ -1,6 +1,10 @@
 use std::{any::TypeId, hash::Hasher, fmt::{Display, Formatter}};
 
+pub mod collider;
+pub mod hierarchy;
+pub mod motion;
 pub mod position;
+pub mod transform;
 
 pub trait Component: 'static + Sized + Send + Sync {
 
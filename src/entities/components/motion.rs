@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use crate::maths::vector::Vector;
+
+/* Pixels per second, added to the Position every tick by apply_motion. Saved as [x, y]. */
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "(f32, f32)", into = "(f32, f32)")]
+pub struct Velocity(pub Vector);
+
+/* Pixels per second squared, added to the Velocity every tick. Saved as [x, y]. */
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "(f32, f32)", into = "(f32, f32)")]
+pub struct Acceleration(pub Vector);
+
+/* Share of its velocity an entity loses per second, 0 slides forever and anything from the tick rate up stops it at once. */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Friction(pub f32);
+
+/* Upper bound on the length of the Velocity, in pixels per second. */
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaxSpeed(pub f32);
+
+macro_rules! vector_conversions {
+    ($( $name:ident )*) => {
+        $(
+            impl From<(f32, f32)> for $name {
+                fn from(vector: (f32, f32)) -> $name {
+                    $name(vector.into())
+                }
+            }
+
+            impl From<$name> for (f32, f32) {
+                fn from(vector: $name) -> (f32, f32) {
+                    (vector.0.x, vector.0.y)
+                }
+            }
+        )*
+    };
+}
+
+vector_conversions! { Velocity Acceleration }
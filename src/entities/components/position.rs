@@ -1 +1,20 @@
-pub struct Position(pub f32, pub f32);
\ No newline at end of file
+use serde::{Deserialize, Serialize};
+
+use crate::maths::vector::Vector;
+
+/* Where an entity is in the world, in pixels. Saved and written in prefabs as [x, y]. */
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "(f32, f32)", into = "(f32, f32)")]
+pub struct Position(pub Vector);
+
+impl From<(f32, f32)> for Position {
+    fn from(position: (f32, f32)) -> Position {
+        Position(position.into())
+    }
+}
+
+impl From<Position> for (f32, f32) {
+    fn from(position: Position) -> (f32, f32) {
+        (position.0.x, position.0.y)
+    }
+}
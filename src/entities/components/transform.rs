@@ -0,0 +1,5 @@
+use crate::maths::transform::Transform;
+
+/* Transform of an entity composed with those of all its parents, written by propagate_transforms. */
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GlobalTransform(pub Transform);
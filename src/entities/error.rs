@@ -0,0 +1,40 @@
+use std::fmt::{Display, Formatter};
+
+use super::EntityId;
+
+/* Why an entity id did not resolve to a living entity. Converts into the String errors used elsewhere. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EntityError {
+    /* The id was never handed out by this world. */
+    NoSuchEntity(EntityId),
+    /* The entity was alive once, its slot has been freed or reused since. */
+    Despawned(EntityId),
+    /* The id was reserved through commands and the spawn has not been applied yet. */
+    NotSpawned(EntityId),
+}
+
+impl EntityError {
+    pub fn id(&self) -> EntityId {
+        match self {
+            EntityError::NoSuchEntity(id) | EntityError::Despawned(id) | EntityError::NotSpawned(id) => *id,
+        }
+    }
+}
+
+impl Display for EntityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntityError::NoSuchEntity(id) => write!(f, "entity {}v{} does not exist", id.index(), id.generation()),
+            EntityError::Despawned(id) => write!(f, "entity {}v{} was despawned", id.index(), id.generation()),
+            EntityError::NotSpawned(id) => write!(f, "entity {}v{} is reserved but not spawned yet", id.index(), id.generation()),
+        }
+    }
+}
+
+impl std::error::Error for EntityError {}
+
+impl From<EntityError> for String {
+    fn from(error: EntityError) -> Self {
+        error.to_string()
+    }
+}
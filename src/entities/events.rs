@@ -0,0 +1,131 @@
+use std::marker::PhantomData;
+
+use super::{resources::{Res, ResMut}, World};
+
+/* One-off occurrences systems tell each other about, like a snowball hitting a zombie. */
+pub trait Event: 'static + Send + Sync {}
+
+impl<T: 'static + Send + Sync> Event for T {}
+
+/* Double buffered queue of one event type, stored as a World resource by World::add_event.
+ * The schedule swaps the buffers at the start of every run, so an event stays readable for the run it was
+ * sent in and the one after, which lets systems ordered before the sender still see it. Then it is dropped.
+ */
+#[derive(Clone)]
+pub struct Events<E: Event> {
+    previous: Vec<E>,
+    current: Vec<E>,
+    /* Number of events sent before the first one in previous. */
+    previous_start: usize,
+    current_start: usize,
+}
+
+impl<E: Event> Default for Events<E> {
+    fn default() -> Self {
+        Events {
+            previous: Vec::new(),
+            current: Vec::new(),
+            previous_start: 0,
+            current_start: 0,
+        }
+    }
+}
+
+impl<E: Event> Events<E> {
+    pub fn send(&mut self, event: E) {
+        self.current.push(event);
+    }
+    /* Drops the events of the previous run and starts a new buffer. */
+    pub fn update(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+        self.previous_start = self.current_start;
+        self.current_start += self.previous.len();
+    }
+    pub fn clear(&mut self) {
+        self.update();
+        self.update();
+    }
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /* Every event sent so far, including the dropped ones. */
+    fn sent(&self) -> usize {
+        self.current_start + self.current.len()
+    }
+    /* Events still buffered that were sent after the first `read` events. */
+    fn unread(&self, read: usize) -> impl Iterator<Item = &E> {
+        let skip_previous = read.saturating_sub(self.previous_start).min(self.previous.len());
+        let skip_current = read.saturating_sub(self.current_start).min(self.current.len());
+        self.previous[skip_previous..].iter().chain(self.current[skip_current..].iter())
+    }
+}
+
+/* How far a reader got, kept by the system between runs so every event is read at most once.
+ * Systems keep it in systems::stateful so rolling back to a snapshot rolls it back too.
+ */
+pub struct EventCursor<E: Event> {
+    read: usize,
+    marker: PhantomData<fn() -> E>,
+}
+
+impl<E: Event> Clone for EventCursor<E> {
+    fn clone(&self) -> Self {
+        EventCursor {
+            read: self.read,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<E: Event> Default for EventCursor<E> {
+    fn default() -> Self {
+        EventCursor {
+            read: 0,
+            marker: PhantomData,
+        }
+    }
+}
+
+pub struct EventWriter<'w, E: Event> {
+    events: ResMut<'w, Events<E>>,
+}
+
+impl<'w, E: Event> EventWriter<'w, E> {
+    pub fn new(world: &'w World) -> Self {
+        EventWriter {
+            events: world.resource_mut().unwrap_or_else(|| panic!("{} was never added as an event", std::any::type_name::<E>())),
+        }
+    }
+    pub fn send(&mut self, event: E) {
+        self.events.send(event);
+    }
+}
+
+pub struct EventReader<'w, 's, E: Event> {
+    events: Res<'w, Events<E>>,
+    cursor: &'s mut EventCursor<E>,
+}
+
+impl<'w, 's, E: Event> EventReader<'w, 's, E> {
+    pub fn new(world: &'w World, cursor: &'s mut EventCursor<E>) -> Self {
+        EventReader {
+            events: world.resource().unwrap_or_else(|| panic!("{} was never added as an event", std::any::type_name::<E>())),
+            cursor,
+        }
+    }
+    /* Events this cursor has not seen yet, oldest first. Events missed for more than a run are lost. */
+    pub fn read(&mut self) -> impl Iterator<Item = &E> {
+        let read = self.cursor.read;
+        self.cursor.read = self.events.sent();
+        self.events.unread(read)
+    }
+}
+
+pub(super) fn update_events<E: Event>(world: &mut World) {
+    if let Some(mut events) = world.resource_mut::<Events<E>>() {
+        events.update();
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::{Display, Formatter},
+    hash::{Hash, Hasher},
+    mem::size_of,
+};
+
+use sdl2::{pixels::Color, rect::Rect, render::{BlendMode, WindowCanvas}};
+use serde::Serialize;
+
+use super::{archetype::{Archetype, ArchetypeId, ComponentTicks}, EntityId, World};
+
+/* Snapshot of everything inside a World, taken by World::inspect. */
+#[derive(Serialize, Debug, Clone)]
+pub struct WorldDump {
+    pub change_tick: u32,
+    pub archetypes: Vec<ArchetypeDump>,
+    pub entities: Vec<EntityDump>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ArchetypeDump {
+    pub id: ArchetypeId,
+    pub components: Vec<String>,
+    pub rows: usize,
+    /* Bytes allocated for the rows, the ticks and the entity ids, spare capacity included. */
+    pub bytes: usize,
+}
+
+/* Components stored in sparse sets are listed after the ones of the archetype. */
+#[derive(Serialize, Debug, Clone)]
+pub struct EntityDump {
+    pub id: EntityId,
+    pub archetype: ArchetypeId,
+    pub components: Vec<ComponentDump>,
+}
+
+/* The value is only there for components registered for Debug output or for saving. */
+#[derive(Serialize, Debug, Clone)]
+pub struct ComponentDump {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl WorldDump {
+    pub(super) fn new(world: &World) -> Self {
+        let mut archetypes = Vec::new();
+        let mut entities = Vec::new();
+        for (id, archetype) in world.archetypes.iter().enumerate() {
+            let id = id as ArchetypeId;
+            let archetype = archetype.borrow();
+            archetypes.push(ArchetypeDump {
+                id,
+                components: archetype.signature.iter().map(|name| name.to_string()).collect(),
+                rows: archetype.entity_ids.len(),
+                bytes: footprint(&archetype),
+            });
+            for (row, entity) in archetype.entity_ids.iter().enumerate() {
+                entities.push(EntityDump {
+                    id: *entity,
+                    archetype: id,
+                    components: archetype
+                        .signature
+                        .iter()
+                        .map(|name| ComponentDump {
+                            name: name.to_string(),
+                            value: world.registry.describe(name, &archetype.components[name], row),
+                        })
+                        .collect(),
+                });
+            }
+        }
+        entities.sort_by_key(|entity| entity.id);
+        let mut sparse: Vec<_> = world.sparse_sets.iter().collect();
+        sparse.sort_by_key(|(name, _)| **name);
+        for (name, set) in sparse {
+            let set = set.borrow();
+            for (row, id) in set.entity_ids.iter().enumerate() {
+                if let Ok(entity) = entities.binary_search_by_key(id, |entity| entity.id) {
+                    entities[entity].components.push(ComponentDump {
+                        name: name.to_string(),
+                        value: world.registry.describe(name, &set.column, row),
+                    });
+                }
+            }
+        }
+        WorldDump {
+            change_tick: world.change_tick(),
+            archetypes,
+            entities,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /* Bars drawn over the top left of the screen, one per archetype. Each starts with a square per component,
+     * coloured the same for the same component in every archetype, followed by a bar as long as its row count
+     * relative to the largest archetype. There is no font to draw names with, print the dump for those.
+     */
+    pub fn render_overlay(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        const ROW_HEIGHT: u32 = 6;
+        const MAX_BAR: u32 = 200;
+        let most_rows = self.archetypes.iter().map(|archetype| archetype.rows).max().unwrap_or(0).max(1);
+        let widest = self.archetypes.iter().map(|archetype| archetype.components.len()).max().unwrap_or(0) as u32;
+        let bar_x = 4 + widest * ROW_HEIGHT + 4;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        canvas.fill_rect(Rect::new(0, 0, bar_x + MAX_BAR + 4, self.archetypes.len() as u32 * ROW_HEIGHT + 8))?;
+        for (i, archetype) in self.archetypes.iter().enumerate() {
+            let y = 4 + (i as u32 * ROW_HEIGHT) as i32;
+            for (j, component) in archetype.components.iter().enumerate() {
+                canvas.set_draw_color(component_colour(component));
+                canvas.fill_rect(Rect::new(4 + (j as u32 * ROW_HEIGHT) as i32, y, ROW_HEIGHT - 1, ROW_HEIGHT - 1))?;
+            }
+            let length = (archetype.rows * MAX_BAR as usize / most_rows) as u32;
+            if length > 0 {
+                canvas.set_draw_color(Color::RGB(220, 220, 220));
+                canvas.fill_rect(Rect::new(bar_x as i32, y, length, ROW_HEIGHT - 1))?;
+            }
+        }
+        canvas.set_blend_mode(BlendMode::None);
+        Ok(())
+    }
+}
+
+impl Display for WorldDump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "World at tick {}, {} archetypes, {} entities", self.change_tick, self.archetypes.len(), self.entities.len())?;
+        for archetype in &self.archetypes {
+            writeln!(f, "archetype {}: {} rows, {} bytes [{}]", archetype.id, archetype.rows, archetype.bytes, archetype.components.join(", "))?;
+        }
+        for entity in &self.entities {
+            writeln!(f, "entity {}v{} in archetype {}", entity.id.index(), entity.id.generation(), entity.archetype)?;
+            for component in &entity.components {
+                match &component.value {
+                    Some(value) => writeln!(f, "    {}: {}", component.name, value)?,
+                    None => writeln!(f, "    {}", component.name)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn footprint(archetype: &Archetype) -> usize {
+    let columns: usize = archetype
+        .components
+        .values()
+        .map(|column| column.data.capacity() * column.data.element_layout().size() + column.ticks.capacity() * size_of::<ComponentTicks>())
+        .sum();
+    columns + archetype.entity_ids.capacity() * size_of::<EntityId>()
+}
+
+fn component_colour(name: &str) -> Color {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    let [r, g, b, ..] = hasher.finish().to_le_bytes();
+    Color::RGB(r | 0x40, g | 0x40, b | 0x40)
+}
@@ -1,19 +1,99 @@
 
-use std::{collections::{HashMap, hash_map::DefaultHasher, HashSet}, hash::{Hash, Hasher}, mem::{swap, replace}, cell::RefCell};
-use any_vec::AnyVec;
-use serde::Serializer;
+use std::{any::{Any, TypeId}, collections::{HashMap, HashSet}, io::{Read, Write}, mem::swap, sync::{Arc, Mutex, PoisonError}};
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
 
-use self::components::{ComponentTypeId, Component};
+use crate::assets::prefab::PrefabManager;
 
+pub use self::archetype::{Archetype, ArchetypeId, Column, ComponentTicks};
+use self::bundle::{Bundle, SpawnSink};
+use self::cell::SyncCell;
+use self::commands::{CommandQueue, Commands};
+use self::component_ref::{ComponentSet, Ref, RefMut};
+use self::error::EntityError;
+use self::events::{Event, EventCursor, EventReader, EventWriter, Events};
+use self::inspector::WorldDump;
+use self::observers::{Lifecycle, ObserverId, Observers};
+use self::components::{ComponentTypeId, Component, hierarchy::{Children, Parent}};
+use self::resources::{Res, ResMut, Resource, Resources};
+use self::serialization::{ComponentRegistry, SaveFormat};
+use self::snapshot::Snapshot;
+use self::sparse_set::SparseSet;
+use self::systems::{IntoQuery, Query, filter::QueryFilter, schedule::Schedule};
+
+pub mod archetype;
+pub mod bundle;
+pub mod cell;
+pub mod commands;
+pub mod component_ref;
+pub mod error;
+pub mod events;
+pub mod inspector;
+pub mod observers;
 pub mod components;
-mod systems;
-pub const VOID_ARCHETYPE: u64 = u64::MAX;
-type EntityId = u32;
+pub mod resources;
+pub mod serialization;
+pub mod snapshot;
+pub mod sparse_set;
+pub mod systems;
+/* The archetype of entities without any component, always the first one created. */
+pub const VOID_ARCHETYPE: ArchetypeId = 0;
+
+/* Handle to an entity. The index is a slot that gets reused after a despawn,
+ * the generation is bumped every time that happens so stale handles stop resolving.
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EntityId {
+	index: u32,
+	generation: u32,
+}
+
+impl EntityId {
+	pub fn index(&self) -> u32 {
+		self.index
+	}
+	pub fn generation(&self) -> u32 {
+		self.generation
+	}
+}
+
+/* Hands out entity ids. Ids can be reserved through a shared World and only become alive once spawned. */
+#[derive(Default, Clone)]
+struct EntityAllocator {
+	generations: Vec<u32>,
+	free_indices: Vec<u32>,
+}
+
+impl EntityAllocator {
+	fn reserve(&mut self) -> EntityId {
+		match self.free_indices.pop() {
+			Some(index) => EntityId { index, generation: self.generations[index as usize] },
+			None => {
+				self.generations.push(0);
+				EntityId { index: self.generations.len() as u32 - 1, generation: 0 }
+			}
+		}
+	}
+	fn free(&mut self, id: EntityId) {
+		self.generations[id.index as usize] += 1;
+		self.free_indices.push(id.index);
+	}
+}
+
 pub struct World {
-	archetypes: HashMap<u64, RefCell<Archetype>>,
-	archetype_sets: HashMap<ComponentTypeId, HashSet<u64>>,
-	entities: HashMap<EntityId, EntityPointer>,
-	entity_count: u32,
+	archetypes: Vec<SyncCell<Archetype>>,
+	archetype_ids: HashMap<Vec<ComponentTypeId>, ArchetypeId>,
+	archetype_sets: HashMap<ComponentTypeId, HashSet<ArchetypeId>>,
+	bundle_archetypes: HashMap<TypeId, ArchetypeId>,
+	entities: EntityTable,
+	sparse_sets: HashMap<ComponentTypeId, SyncCell<SparseSet>>,
+	allocator: Mutex<EntityAllocator>,
+	change_tick: u32,
+	last_run: u32,
+	resources: Resources,
+	event_updaters: Vec<fn(&mut World)>,
+	registry: ComponentRegistry,
+	observers: Observers,
 }
 
 impl World {
@@ -22,193 +102,877 @@ impl World {
 
 	pub fn init() -> Self {
 		let mut world = World {
-			entities: HashMap::new(),
-			archetypes: HashMap::new(),
+			entities: EntityTable::default(),
+			archetypes: Vec::new(),
+			archetype_ids: HashMap::new(),
 			archetype_sets: HashMap::new(),
-			entity_count: 0
+			bundle_archetypes: HashMap::new(),
+			sparse_sets: HashMap::new(),
+			allocator: Mutex::new(EntityAllocator::default()),
+			change_tick: 1,
+			last_run: 0,
+			resources: Resources::default(),
+			event_updaters: Vec::new(),
+			registry: ComponentRegistry::default(),
+			observers: Observers::default(),
 		};
 
-		world.archetypes.insert(VOID_ARCHETYPE, RefCell::new(Archetype {
-					components: HashMap::new(),
-					entity_ids: Vec::new(),
-				}));
+		world.intern_archetype(Vec::new(), HashMap::new());
 		world
 	}
 
 	pub fn new_entity(&mut self) -> EntityId {
-		let new_id = self.entity_count;
-		self.entity_count += 1;
-		let mut void_archetype = self.archetypes.get_mut(&VOID_ARCHETYPE).expect("Void archetype was not initialized!").borrow_mut();
-		let new_row = void_archetype.new_row(new_id);
-		let void_pointer = EntityPointer {
-			archetype_id: VOID_ARCHETYPE,
-			index: new_row
-		};
-		self.entities.insert(new_id, void_pointer);
+		self.spawn(())
+	}
+	pub fn spawn<B: Bundle>(&mut self, bundle: B) -> EntityId {
+		let new_id = self.reserve_entity();
+		self.spawn_reserved(new_id, bundle);
 		new_id
 	}
+	/* The id is not alive until a spawn command for it is applied. */
+	pub fn reserve_entity(&self) -> EntityId {
+		self.allocator.lock().unwrap_or_else(PoisonError::into_inner).reserve()
+	}
+	fn spawn_reserved<B: Bundle>(&mut self, new_id: EntityId, bundle: B) {
+		let archetype_id = match self.bundle_archetypes.get(&TypeId::of::<B>()) {
+			Some(archetype_id) => *archetype_id,
+			None => {
+				let mut signature = B::component_ids();
+				signature.sort();
+				for pair in signature.windows(2) {
+					assert!(pair[0] != pair[1], "{} appears more than once in the same bundle", pair[0]);
+				}
+				signature.retain(|name| !self.sparse_sets.contains_key(name));
+				let mut columns = B::columns();
+				columns.retain(|name, _| !self.sparse_sets.contains_key(name));
+				let archetype_id = self.intern_archetype(signature, columns);
+				self.bundle_archetypes.insert(TypeId::of::<B>(), archetype_id);
+				archetype_id
+			}
+		};
+		let mut archetype = self.archetypes.get(archetype_id as usize).unwrap().borrow_mut();
+		let new_row = archetype.new_row(new_id);
+		bundle.push(&mut SpawnSink {
+			archetype: &mut archetype,
+			sparse_sets: &mut self.sparse_sets,
+			id: new_id,
+			tick: self.change_tick,
+		});
+		drop(archetype);
+		self.entities.insert(new_id, EntityPointer {
+			archetype_id,
+			index: new_row
+		});
+		if !self.observers.is_empty() {
+			self.notify(Lifecycle::Add, &B::component_ids(), new_id);
+		}
+	}
+	/* Spawns a reserved id with components given as one-row columns, like the ones ComponentRegistration::deserialize
+	 * makes, putting it straight into its final archetype.
+	 */
+	fn spawn_columns(&mut self, new_id: EntityId, mut components: Vec<(ComponentTypeId, Column)>) {
+		components.sort_by_key(|(component, _)| *component);
+		for pair in components.windows(2) {
+			assert!(pair[0].0 != pair[1].0, "{} appears more than once in the same spawn", pair[0].0);
+		}
+		let added: Vec<ComponentTypeId> = components.iter().map(|(component, _)| *component).collect();
+		let (sparse, dense): (Vec<_>, Vec<_>) = components.into_iter().partition(|(component, _)| self.sparse_sets.contains_key(component));
+		let signature = dense.iter().map(|(component, _)| *component).collect();
+		let columns = dense.iter().map(|(component, column)| (*component, column.clone_empty())).collect();
+		let archetype_id = self.intern_archetype(signature, columns);
+		let tick = self.change_tick;
+		let archetype = self.archetypes[archetype_id as usize].get_mut();
+		let new_row = archetype.new_row(new_id);
+		for (component, mut column) in dense {
+			let target = archetype.components.get_mut(&component).unwrap();
+			column.move_row(0, target);
+			target.ticks[new_row] = ComponentTicks::new(tick);
+		}
+		for (component, mut column) in sparse {
+			self.sparse_sets.get_mut(&component).unwrap().get_mut().push_column(new_id, &mut column, tick);
+		}
+		self.entities.insert(new_id, EntityPointer {
+			archetype_id,
+			index: new_row
+		});
+		if !self.observers.is_empty() {
+			self.notify(Lifecycle::Add, &added, new_id);
+		}
+	}
+	pub fn is_alive(&self, id: EntityId) -> bool {
+		self.entities.contains(id)
+	}
+	pub fn entity_count(&self) -> usize {
+		self.entities.len()
+	}
+	/* Despawns the children of the entity as well, all the way down. */
+	pub fn despawn(&mut self, id: EntityId) -> Result<(), String> {
+		if !self.is_alive(id) {
+			return Err("entity does not exist".to_string());
+		}
+		self.remove_parent(id)?;
+		let mut queue = CommandQueue::new();
+		let despawned = self.despawn_tree(id, &mut queue);
+		queue.apply(self);
+		despawned
+	}
+	/* Remove observers run for every entity before any of the tree is gone, their commands are left in queue. */
+	fn despawn_tree(&mut self, id: EntityId, queue: &mut CommandQueue) -> Result<(), String> {
+		if !self.observers.is_empty() {
+			self.observers.trigger(self, Lifecycle::Remove, &self.components_of(id), id, queue);
+		}
+		if let Some(children) = self.clone_component::<Children>(id)? {
+			// a child whose Parent was swapped out behind set_parent's back may be gone already
+			for child in children.0 {
+				if self.is_alive(child) {
+					self.despawn_tree(child, queue)?;
+				}
+			}
+		}
+		let pointer = self.entities.remove(id).ok_or("entity does not exist")?;
+		for set in self.sparse_sets.values_mut() {
+			set.get_mut().remove_entity(id);
+		}
+		let swapped = self.archetypes.get(pointer.archetype_id as usize).ok_or("entity has no archetype")?.borrow_mut().swap_remove(pointer.index);
+		if swapped != id {
+			self.entities.get_mut(swapped).expect("swapped entity was not in the entity table").index = pointer.index;
+		}
+		self.allocator.get_mut().unwrap_or_else(PoisonError::into_inner).free(id);
+		Ok(())
+	}
+	/* Moves child under parent, taking it away from its previous parent if it had one. */
+	pub fn set_parent(&mut self, child: EntityId, parent: EntityId) -> Result<(), String> {
+		if !self.is_alive(child) || !self.is_alive(parent) {
+			return Err("entity does not exist".to_string());
+		}
+		let mut ancestor = Some(parent);
+		while let Some(id) = ancestor {
+			if id == child {
+				return Err(format!("{:?} cannot become a child of its own descendant {:?}", child, parent));
+			}
+			ancestor = self.clone_component::<Parent>(id)?.map(|parent| parent.0);
+		}
+		self.remove_parent(child)?;
+		self.set_component(child, Parent(parent))?;
+		let mut children = self.clone_component::<Children>(parent)?.unwrap_or_default();
+		children.0.push(child);
+		self.set_component(parent, children)?;
+		Ok(())
+	}
+	/* Makes the entity a root again, returns the parent it had. */
+	pub fn remove_parent(&mut self, child: EntityId) -> Result<Option<EntityId>, String> {
+		Ok(self.remove_component::<Parent>(child)?.map(|parent| parent.0))
+	}
+	/* Takes child out of the Children of parent, dropping the component once it is empty. */
+	fn unlink_child(&mut self, parent: EntityId, child: EntityId) -> Result<(), String> {
+		if !self.is_alive(parent) {
+			return Ok(());
+		}
+		if let Some(mut children) = self.clone_component::<Children>(parent)? {
+			children.0.retain(|id| *id != child);
+			if children.0.is_empty() {
+				self.remove_component::<Children>(parent)?;
+			} else {
+				self.set_component(parent, children)?;
+			}
+		}
+		Ok(())
+	}
 	pub fn clone_component<T: Component + Clone>(&self, id: EntityId) -> Result<Option<T>, String> {
-		let pointer = self.archetype_id_from_entity(id).ok_or("entity does not exist")?;
-		let archetype = self.archetypes.get(&pointer.archetype_id).ok_or("entity has no archetype")?.borrow();
-		let val = archetype.components.get(&ComponentTypeId::of::<T>()).and_then(|v| v.downcast_ref::<T>()?.get(pointer.index)).and_then(|val| Some(val.clone()));
-
-		Ok(val)
+		Ok(self.get::<T>(id)?.map(|component| component.clone()))
+	}
+	/* Borrows the component in place, see Ref. */
+	pub fn get<T: Component>(&self, id: EntityId) -> Result<Option<Ref<'_, T>>, EntityError> {
+		let pointer = self.entities.get(id).ok_or_else(|| self.entity_error(id))?;
+		if let Some(set) = self.sparse_sets.get(&ComponentTypeId::of::<T>()) {
+			return Ok(Ref::sparse(set.borrow(), id));
+		}
+		Ok(Ref::archetype(self.archetypes[pointer.archetype_id as usize].borrow(), pointer.index))
+	}
+	/* Writes through the returned RefMut are stamped with the current change tick. */
+	pub fn get_mut<T: Component>(&self, id: EntityId) -> Result<Option<RefMut<'_, T>>, EntityError> {
+		let pointer = self.entities.get(id).ok_or_else(|| self.entity_error(id))?;
+		if let Some(set) = self.sparse_sets.get(&ComponentTypeId::of::<T>()) {
+			return Ok(RefMut::sparse(set.borrow_mut(), id, self.change_tick));
+		}
+		Ok(RefMut::archetype(self.archetypes[pointer.archetype_id as usize].borrow_mut(), pointer.index, self.change_tick))
+	}
+	/* Borrows several components of the entity at once, None unless it has all of them. */
+	pub fn get_many<C: ComponentSet>(&self, id: EntityId) -> Result<Option<C::Refs<'_>>, EntityError> {
+		C::get(self, id)
+	}
+	/* Does not borrow anything, so it works while the entity's archetype is borrowed by a query. */
+	pub fn has<T: Component>(&self, id: EntityId) -> Result<bool, EntityError> {
+		let pointer = self.entities.get(id).ok_or_else(|| self.entity_error(id))?;
+		let name = ComponentTypeId::of::<T>();
+		if let Some(set) = self.sparse_sets.get(&name) {
+			return Ok(set.borrow().contains(id));
+		}
+		Ok(self.archetype_sets.get(&name).is_some_and(|archetypes| archetypes.contains(&pointer.archetype_id)))
+	}
+	/* Why an id that is not in the entity table does not resolve. */
+	fn entity_error(&self, id: EntityId) -> EntityError {
+		let allocator = self.allocator.lock().unwrap_or_else(PoisonError::into_inner);
+		match allocator.generations.get(id.index as usize) {
+			None => EntityError::NoSuchEntity(id),
+			Some(generation) if *generation != id.generation => EntityError::Despawned(id),
+			Some(_) => EntityError::NotSpawned(id),
+		}
+	}
+	pub fn query<Q: IntoQuery>(&self) -> Query<'_, Q> {
+		Q::query(self)
+	}
+	pub fn query_filtered<Q: IntoQuery, F: QueryFilter>(&self) -> Query<'_, Q, F> {
+		Query::new(self, self.last_run)
+	}
+	/* Id the next archetype will get. Every archetype below it exists, none is ever destroyed. */
+	pub fn archetype_generation(&self) -> ArchetypeId {
+		self.archetypes.len() as ArchetypeId
+	}
+	/* Tick every component write is stamped with. The schedule advances it after each system. */
+	pub fn change_tick(&self) -> u32 {
+		self.change_tick
+	}
+	/* Added<T> and Changed<T> only see what was written after this tick.
+	 * While a system runs it is the tick of that system's previous run, outside the schedule
+	 * it is the tick just before the last schedule run started, or the last advance_tick.
+	 */
+	pub fn last_run(&self) -> u32 {
+		self.last_run
+	}
+	/* Marks everything written so far as seen for queries made outside the schedule. */
+	pub fn advance_tick(&mut self) {
+		self.last_run = self.change_tick;
+		self.change_tick += 1;
+	}
+	pub fn insert_resource<R: Resource>(&mut self, resource: R) -> Option<R> {
+		self.resources.insert(resource)
+	}
+	pub fn remove_resource<R: Resource>(&mut self) -> Option<R> {
+		self.resources.remove()
+	}
+	pub fn has_resource<R: Resource>(&self) -> bool {
+		self.resources.contains::<R>()
+	}
+	pub fn resource<R: Resource>(&self) -> Option<Res<'_, R>> {
+		self.resources.get()
+	}
+	pub fn resource_mut<R: Resource>(&self) -> Option<ResMut<'_, R>> {
+		self.resources.get_mut()
+	}
+	/* Stores Events<E> as a resource and has the schedule swap its buffers every run. */
+	pub fn add_event<E: Event>(&mut self) {
+		if !self.has_resource::<Events<E>>() {
+			self.insert_resource(Events::<E>::default());
+			self.event_updaters.push(events::update_events::<E>);
+		}
+	}
+	pub fn event_writer<E: Event>(&self) -> EventWriter<'_, E> {
+		EventWriter::new(self)
+	}
+	pub fn event_reader<'s, E: Event>(&self, cursor: &'s mut EventCursor<E>) -> EventReader<'_, 's, E> {
+		EventReader::new(self, cursor)
+	}
+	pub fn update_events(&mut self) {
+		for update in self.event_updaters.clone() {
+			update(self);
+		}
+	}
+	/* Opts a component into World::save and World::load under a name that must not change between versions. */
+	pub fn register_component<T: Component + Serialize + DeserializeOwned>(&mut self, name: &'static str) -> Result<(), String> {
+		self.registry.register::<T>(name)
+	}
+	/* Stores T in a sparse set instead of the archetypes from now on, so adding and removing it never moves the entity.
+	 * Must be done before any entity gets a T. Sparse components can only be queried through filters.
+	 */
+	pub fn register_sparse<T: Component>(&mut self) -> Result<(), String> {
+		let name = ComponentTypeId::of::<T>();
+		if self.archetype_sets.contains_key(&name) {
+			return Err(format!("{} is already stored in archetypes", name));
+		}
+		self.sparse_sets.entry(name).or_insert_with(|| SyncCell::new(SparseSet::new::<T>()));
+		Ok(())
+	}
+	pub fn is_sparse(&self, component: &ComponentTypeId) -> bool {
+		self.sparse_sets.contains_key(component)
+	}
+	/* Calls observer every time event happens to a T on any entity, see Lifecycle for when exactly. */
+	pub fn observe<T: Component>(&mut self, event: Lifecycle, observer: impl Fn(&World, EntityId, &mut Commands) + Send + Sync + 'static) -> ObserverId {
+		self.observers.add(ComponentTypeId::of::<T>(), event, Arc::new(observer))
+	}
+	pub fn on_add<T: Component>(&mut self, observer: impl Fn(&World, EntityId, &mut Commands) + Send + Sync + 'static) -> ObserverId {
+		self.observe::<T>(Lifecycle::Add, observer)
+	}
+	pub fn on_replace<T: Component>(&mut self, observer: impl Fn(&World, EntityId, &mut Commands) + Send + Sync + 'static) -> ObserverId {
+		self.observe::<T>(Lifecycle::Replace, observer)
+	}
+	pub fn on_remove<T: Component>(&mut self, observer: impl Fn(&World, EntityId, &mut Commands) + Send + Sync + 'static) -> ObserverId {
+		self.observe::<T>(Lifecycle::Remove, observer)
+	}
+	pub fn unobserve(&mut self, id: ObserverId) -> bool {
+		self.observers.remove(id)
+	}
+	/* Lets the inspector show the component's Debug output. */
+	pub fn register_debug<T: Component + std::fmt::Debug>(&mut self) {
+		self.registry.register_debug::<T>();
+	}
+	/* Every archetype and entity, printable as text or JSON. */
+	pub fn inspect(&self) -> WorldDump {
+		WorldDump::new(self)
+	}
+	pub fn registry(&self) -> &ComponentRegistry {
+		&self.registry
+	}
+	/* Writes every entity with its registered components. */
+	pub fn save<W: Write>(&self, writer: W, format: SaveFormat) -> Result<(), String> {
+		serialization::save(self, writer, format)
+	}
+	/* Replaces every entity with the ones from a save, ids included. Resources are kept. */
+	pub fn load<R: Read>(&mut self, reader: R, format: SaveFormat) -> Result<(), String> {
+		serialization::load(self, reader, format)
+	}
+	/* Lets snapshots copy the component, every component an entity has must be registered for World::snapshot to work. */
+	pub fn register_clone<T: Component + Clone>(&mut self) {
+		self.registry.register_clone::<T>();
+	}
+	/* Has snapshots copy the resource and restoring them put it back. Other resources are not touched by restore. */
+	pub fn register_clone_resource<R: Resource + Clone>(&mut self) {
+		self.resources.register_clone::<R>();
+	}
+	/* Copies every entity and registered resource, see Snapshot. */
+	pub fn snapshot(&self) -> Result<Snapshot, String> {
+		snapshot::take(self)
+	}
+	/* Puts the world back the way it was when the snapshot was taken, entity ids and ticks included.
+	 * Observers do not run. A Schedule keeps its own ticks, see SnapshotRing for rewinding both.
+	 */
+	pub fn restore(&mut self, snapshot: &Snapshot) {
+		snapshot::restore(self, snapshot);
+	}
+	/* Spawns the prefab with that name, loaded through the PrefabManager resource. */
+	pub fn spawn_prefab(&mut self, name: &str) -> Result<EntityId, String> {
+		self.spawn_prefab_with(name, &Map::new())
+	}
+	/* Overrides are merged into the prefab's components, see Prefab::with_overrides. */
+	pub fn spawn_prefab_with(&mut self, name: &str, overrides: &Map<String, Value>) -> Result<EntityId, String> {
+		let prefab = self.resource_mut::<PrefabManager>().ok_or("the world has no PrefabManager")?.load(name)?;
+		let mut components = Vec::new();
+		for (component, value) in prefab.with_overrides(overrides) {
+			let registration = self.registry.get_by_name(&component).ok_or(format!("prefab {}: no component is registered as {}", name, component))?;
+			let column = registration.deserialize(value).map_err(|e| format!("prefab {}: {}: {}", name, component, e))?;
+			components.push((registration.component(), column));
+		}
+		let id = self.reserve_entity();
+		self.spawn_columns(id, components);
+		Ok(id)
+	}
+	/* Despawns everything at once. Archetypes stay around, empty.
+	 * Remove observers still run for every component, their commands are applied before anything is cleared.
+	 */
+	pub fn clear_entities(&mut self) {
+		if !self.observers.is_empty() {
+			let mut queue = CommandQueue::new();
+			for id in self.entities.ids() {
+				self.observers.trigger(self, Lifecycle::Remove, &self.components_of(id), id, &mut queue);
+			}
+			queue.apply(self);
+		}
+		for archetype in self.archetypes.iter_mut() {
+			archetype.get_mut().clear();
+		}
+		for set in self.sparse_sets.values_mut() {
+			set.get_mut().clear();
+		}
+		let allocator = self.allocator.get_mut().unwrap_or_else(PoisonError::into_inner);
+		for id in self.entities.drain() {
+			allocator.free(id);
+		}
+	}
+	/* Runs one fixed tick worth of systems. */
+	pub fn run_schedule(&mut self, schedule: &mut Schedule) {
+		schedule.run(self);
 	}
 	fn archetype_id_from_entity(&self, id: EntityId) -> Option<&EntityPointer> {
-		self.entities.get(&id)
+		self.entities.get(id)
 	}
+	/* Triggers Add observers if the entity did not have a T yet, Replace observers if it did. */
 	pub fn set_component<T: Component>(&mut self, id: EntityId, component: T) -> Result<Option<T>, String> {
+		let old = self.insert_component(id, component)?;
+		let event = if old.is_some() { Lifecycle::Replace } else { Lifecycle::Add };
+		self.notify(event, &[ComponentTypeId::of::<T>()], id);
+		Ok(old)
+	}
+	fn insert_component<T: Component>(&mut self, id: EntityId, component: T) -> Result<Option<T>, String> {
 		let name = ComponentTypeId::of::<T>();
-		let archetype_id = self.archetype_id_from_entity(id).ok_or("entity does not exist")?.archetype_id;
-		let archetype = self.archetypes.get(&archetype_id).ok_or("entity has no archetype")?.borrow();
-		let old_hash = archetype_id;
-		let has_already = archetype.components.contains_key(&name);
-		let new_hash = {
-			if has_already {
-				old_hash
-			} else {
-				let mut hasher = DefaultHasher::new();
-				name.hash(&mut hasher);
-				old_hash ^ hasher.finish()
-			}
+		let pointer = self.archetype_id_from_entity(id).ok_or("entity does not exist")?.clone();
+		if let Some(set) = self.sparse_sets.get_mut(&name) {
+			return set.get_mut().insert(id, component, self.change_tick);
+		}
+		let mut archetype = self.archetypes.get(pointer.archetype_id as usize).ok_or("entity has no archetype")?.borrow_mut();
+		if archetype.components.contains_key(&name) {
+			return archetype.set::<T>(pointer.index, component, self.change_tick).map(Some);
+		}
+		drop(archetype);
+		let target = self.archetype_with::<T>(pointer.archetype_id);
+		self.move_entity(id, target)?;
+		self.archetypes.get(target as usize).unwrap().borrow_mut().push(component, self.change_tick)?;
+		Ok(None)
+	}
+	/* Remove observers run while the entity still has the component, their commands are applied once it is gone.
+	 * Removing a Parent takes the entity out of the parent's Children too, like remove_parent.
+	 */
+	pub fn remove_component<T: Component>(&mut self, id: EntityId) -> Result<Option<T>, String> {
+		let mut queue = CommandQueue::new();
+		if !self.observers.is_empty() && self.has::<T>(id)? {
+			self.observers.trigger(self, Lifecycle::Remove, &[ComponentTypeId::of::<T>()], id, &mut queue);
+		}
+		let removed = self.take_component::<T>(id);
+		if let Some(Ok(Some(parent))) = (&removed as &dyn Any).downcast_ref::<Result<Option<Parent>, String>>() {
+			self.unlink_child(parent.0, id)?;
+		}
+		queue.apply(self);
+		removed
+	}
+	fn take_component<T: Component>(&mut self, id: EntityId) -> Result<Option<T>, String> {
+		let name = ComponentTypeId::of::<T>();
+		let pointer = self.archetype_id_from_entity(id).ok_or("entity does not exist")?.clone();
+		if let Some(set) = self.sparse_sets.get_mut(&name) {
+			return set.get_mut().remove::<T>(id);
+		}
+		if !self.archetypes.get(pointer.archetype_id as usize).ok_or("entity has no archetype")?.borrow().components.contains_key(&name) {
+			return Ok(None);
+		}
+		let target = self.archetype_without(pointer.archetype_id, name);
+		let (_, mut removed) = self.move_entity(id, target)?;
+		let mut storage = removed.remove(&name).expect("removed component was not moved out of the archetype");
+		let value = storage.data.downcast_mut::<T>().ok_or(format!("AnyVec in wrong row, should be of type {:?}", name))?.swap_remove(0);
+		Ok(Some(value))
+	}
+
+	/* Every component the entity has, sparse ones included. */
+	fn components_of(&self, id: EntityId) -> Vec<ComponentTypeId> {
+		let mut components = match self.entities.get(id) {
+			Some(pointer) => self.archetypes[pointer.archetype_id as usize].borrow().signature.clone(),
+			None => return Vec::new(),
 		};
+		components.extend(self.sparse_sets.iter().filter(|(_, set)| set.borrow().contains(id)).map(|(name, _)| *name));
+		components
+	}
+
+	/* Runs the observers for something that just happened and applies their commands. */
+	fn notify(&mut self, event: Lifecycle, components: &[ComponentTypeId], id: EntityId) {
+		if self.observers.is_empty() {
+			return;
+		}
+		let mut queue = CommandQueue::new();
+		self.observers.trigger(self, event, components, id, &mut queue);
+		queue.apply(self);
+	}
+
+	/* Returns the archetype whose sorted component set is signature, creating it from the given columns if there is none yet. */
+	fn intern_archetype(&mut self, signature: Vec<ComponentTypeId>, components: HashMap<ComponentTypeId, Column>) -> ArchetypeId {
+		if let Some(archetype_id) = self.archetype_ids.get(&signature) {
+			return *archetype_id;
+		}
+		let archetype_id = self.archetypes.len() as ArchetypeId;
+		for name in &signature {
+			self.archetype_sets.entry(*name).or_default().insert(archetype_id);
+		}
+		self.archetype_ids.insert(signature.clone(), archetype_id);
+		self.archetypes.push(SyncCell::new(Archetype::new(signature, components)));
+		archetype_id
+	}
+
+	/* Follows the add edge for T out of an archetype that does not have T, building the edge the first time. */
+	fn archetype_with<T: Component>(&mut self, from: ArchetypeId) -> ArchetypeId {
+		let name = ComponentTypeId::of::<T>();
+		let archetype = self.archetypes[from as usize].borrow();
+		if let Some(target) = archetype.edges.add.get(&name) {
+			return *target;
+		}
+		let mut signature = archetype.signature.clone();
+		let position = signature.binary_search(&name).expect_err("archetype already has the added component");
+		signature.insert(position, name);
+		let mut components: HashMap<ComponentTypeId, Column> = archetype.components.iter().map(|(name, storage)| (*name, storage.clone_empty())).collect();
+		components.insert(name, Column::new::<T>());
 		drop(archetype);
-		let archetype_already_exists = self.archetypes.contains_key(&new_hash);
-		let mut archetype = self.archetypes.get(&archetype_id).ok_or("entity has no archetype")?.borrow_mut();
-		let mut current_archetype_storage;
-		if has_already {
-			let ptr = self.entities.get(&id).ok_or("entity does not exist")?;
-			current_archetype_storage = archetype;
-			current_archetype_storage.set::<T>(ptr.index, component).map(|val| Some(val))
-		} else if archetype_already_exists {
-			let new_row = self.archetypes.get(&new_hash).unwrap().borrow_mut().new_row(id);
-			let old_ptr = self.entities.get(&id).ok_or("entity does not exist")?.to_owned();
-			
-
-			let swapped_entity = *archetype.entity_ids.last().unwrap();
-			archetype.entity_ids.swap_remove(old_ptr.index);
-			self.entities.get_mut(&swapped_entity).unwrap().index = old_ptr.index;
-
-			let column_iter = archetype.components.iter_mut();
-			let mut new_archetype = self.archetypes.get(&new_hash).unwrap().borrow_mut();
-			for entry in column_iter {
-				let old_component_storage = entry.1;
-				
-				let mut new_component_storage = new_archetype.components.get_mut(entry.0).expect("New component storage was initialized incorrectly");
-		
-
-
-				let value = old_component_storage.swap_remove(old_ptr.index);
-				
-				new_component_storage.push(value)
-			}
-			current_archetype_storage = self.archetypes.get(&new_hash).unwrap().borrow_mut();
-			current_archetype_storage.entity_ids[new_row] = id;
-			current_archetype_storage.push(component).map(|_| None)
-		} else {
-			let mut new_row;
-			current_archetype_storage = {
-				let mut new_archetype = Archetype { 
-					components: HashMap::new(), 
-					entity_ids: Vec::new()
-				};
-				new_row = new_archetype.new_row(id);
-				let old_ptr = self.entities.get(&id).ok_or("entity does not exist")?.clone();
-				
-
-				let swapped_entity = *archetype.entity_ids.last().unwrap();
-				archetype.entity_ids.swap_remove(old_ptr.index);
-				self.entities.get_mut(&swapped_entity).unwrap().index = old_ptr.index;
-				
-				let column_iter = archetype.components.iter_mut();
-				for entry in column_iter {
-					let old_component_storage = entry.1;
-
-					let mut new_component_storage = old_component_storage.clone_empty();
-	
-					let value = old_component_storage.swap_remove(old_ptr.index);
+		let target = self.intern_archetype(signature, components);
+		self.link_archetypes(from, target, name);
+		target
+	}
 
-					
-					new_component_storage.push(value);
-					
-					new_archetype.components.insert(entry.0.clone(), new_component_storage);
-					self.archetype_sets.get_mut(entry.0).expect("previous archetype was not in correct sets").insert(new_hash);
-				}
-				new_archetype.components.insert(name, AnyVec::new::<T>());
+	/* Follows the remove edge for name out of an archetype that has it, building the edge the first time. */
+	fn archetype_without(&mut self, from: ArchetypeId, name: ComponentTypeId) -> ArchetypeId {
+		let archetype = self.archetypes[from as usize].borrow();
+		if let Some(target) = archetype.edges.remove.get(&name) {
+			return *target;
+		}
+		let signature: Vec<ComponentTypeId> = archetype.signature.iter().copied().filter(|other| *other != name).collect();
+		let components: HashMap<ComponentTypeId, Column> = archetype.components.iter().filter(|(other, _)| **other != name).map(|(name, storage)| (*name, storage.clone_empty())).collect();
+		drop(archetype);
+		let target = self.intern_archetype(signature, components);
+		self.link_archetypes(target, from, name);
+		target
+	}
 
-				new_archetype.push(component);
-				let new_component_set = {
-					match self.archetype_sets.get_mut(&name) {
-						Some(val) => {
-							val
-						},
-						None => {
-							self.archetype_sets.insert(name, HashSet::new());
-							self.archetype_sets.get_mut(&name).unwrap()
-						}
-					}
-				};
-				new_component_set.insert(new_hash);
-				drop(archetype);
+	/* Caches the edge both ways, with is the archetype without plus the component. */
+	fn link_archetypes(&mut self, without: ArchetypeId, with: ArchetypeId, name: ComponentTypeId) {
+		self.archetypes[without as usize].borrow_mut().edges.add.insert(name, with);
+		self.archetypes[with as usize].borrow_mut().edges.remove.insert(name, without);
+	}
 
-				self.archetypes.insert(new_hash, RefCell::new(new_archetype));
-				
-				self.archetypes.get_mut(&new_hash).unwrap().borrow_mut()
-				};
-				Ok(None)
+	/* Moves the entity's row into the target archetype, carrying over every column the two share.
+	 * Columns only the target has must be pushed by the caller.
+	 * Returns the new row index and the values of the columns the target does not have.
+	 */
+	fn move_entity(&mut self, id: EntityId, target: ArchetypeId) -> Result<(usize, HashMap<ComponentTypeId, Column>), String> {
+		let old_ptr = self.entities.get(id).ok_or("entity does not exist")?.clone();
+		let mut old_archetype = self.archetypes.get(old_ptr.archetype_id as usize).ok_or("entity has no archetype")?.borrow_mut();
+		let mut new_archetype = self.archetypes.get(target as usize).ok_or("target archetype does not exist")?.borrow_mut();
+		let new_row = new_archetype.new_row(id);
+		let mut removed = HashMap::new();
 
+		let swapped_entity = *old_archetype.entity_ids.last().unwrap();
+		old_archetype.entity_ids.swap_remove(old_ptr.index);
+		for (name, old_component_storage) in old_archetype.components.iter_mut() {
+			if let Some(new_component_storage) = new_archetype.components.get_mut(name) {
+				old_component_storage.move_row(old_ptr.index, new_component_storage);
+			} else {
+				let mut removed_storage = old_component_storage.clone_empty();
+				old_component_storage.move_row(old_ptr.index, &mut removed_storage);
+				removed.insert(*name, removed_storage);
+			}
+		}
+		drop(old_archetype);
+		drop(new_archetype);
 
-			
+		self.entities.get_mut(swapped_entity).unwrap().index = old_ptr.index;
+		self.entities.insert(id, EntityPointer { archetype_id: target, index: new_row });
+		Ok((new_row, removed))
+	}
+}
+/* Where every living entity's row is, indexed by the slot of its id so a lookup is one bounds check.
+ * The full id is kept next to the pointer so a stale id whose slot got reused does not resolve.
+ */
+#[derive(Default, Clone)]
+struct EntityTable {
+	slots: Vec<Option<(EntityId, EntityPointer)>>,
+	len: usize,
+}
 
+impl EntityTable {
+	fn get(&self, id: EntityId) -> Option<&EntityPointer> {
+		match self.slots.get(id.index as usize) {
+			Some(Some((other, pointer))) if *other == id => Some(pointer),
+			_ => None,
+		}
+	}
+	fn get_mut(&mut self, id: EntityId) -> Option<&mut EntityPointer> {
+		match self.slots.get_mut(id.index as usize) {
+			Some(Some((other, pointer))) if *other == id => Some(pointer),
+			_ => None,
+		}
+	}
+	fn contains(&self, id: EntityId) -> bool {
+		self.get(id).is_some()
+	}
+	fn insert(&mut self, id: EntityId, pointer: EntityPointer) {
+		let index = id.index as usize;
+		if self.slots.len() <= index {
+			self.slots.resize_with(index + 1, || None);
 		}
+		if self.slots[index].replace((id, pointer)).is_none() {
+			self.len += 1;
+		}
+	}
+	fn remove(&mut self, id: EntityId) -> Option<EntityPointer> {
+		self.get(id)?;
+		self.len -= 1;
+		self.slots[id.index as usize].take().map(|(_, pointer)| pointer)
+	}
+	fn len(&self) -> usize {
+		self.len
+	}
+	fn ids(&self) -> impl Iterator<Item = EntityId> + '_ {
+		self.slots.iter().flatten().map(|(id, _)| *id)
+	}
+	/* Empties the table, returning the ids that were in it. */
+	fn drain(&mut self) -> Vec<EntityId> {
+		let ids = self.ids().collect();
+		self.slots.clear();
+		self.len = 0;
+		ids
 	}
 }
+
 #[derive(Clone)]
 struct EntityPointer {
-	archetype_id: u64,
+	archetype_id: ArchetypeId,
 	index: usize
 }
-struct Archetype {
-	components: HashMap<ComponentTypeId, AnyVec>,
-	entity_ids: Vec<EntityId>
-}
 
-impl Archetype {
-	pub fn new_row(&mut self, entity_id: EntityId) -> usize {
-		let new_row_index = self.entity_ids.len();
-		self.entity_ids.push(entity_id);
-		new_row_index
+#[cfg(test)]
+mod tests {
+	use serde_json::{json, Map};
+
+	use crate::assets::prefab::{PrefabLoader, PrefabManager};
+
+	use super::{
+		components::{
+			collider::Collider,
+			hierarchy::{Children, Parent},
+			motion::{Friction, MaxSpeed, Velocity},
+			position::Position,
+		},
+		systems::filter::{With, Without},
+		ComponentTypeId, EntityId, World,
+	};
+
+	#[derive(Debug, PartialEq)]
+	struct A(u32);
+	#[derive(Debug, PartialEq)]
+	struct B(&'static str);
+
+	#[test]
+	fn despawned_slots_are_reused_with_a_new_generation() {
+		let mut world = World::init();
+		let first = world.spawn((A(1),));
+		world.despawn(first).unwrap();
+		assert!(!world.is_alive(first));
+		let second = world.spawn((A(2),));
+		assert_eq!(second.index(), first.index());
+		assert_eq!(second.generation(), first.generation() + 1);
+
+		// the stale id must not resolve to whatever lives in its slot now
+		assert!(world.get::<A>(first).is_err());
+		assert!(world.despawn(first).is_err());
+		assert!(world.set_component(first, B("stale")).is_err());
+		assert!(world.remove_component::<A>(first).is_err());
+		assert_eq!(world.get::<A>(second).unwrap().unwrap().0, 2);
+		assert_eq!(world.entity_count(), 1);
 	}
 
-	/* Caller must fix entity pointers. Returned id is the entity id of the swapped entity.
-	 * New row for the swapped entity is the row_index
-	 */
-	pub fn swap_remove(&mut self, row_index: usize) -> EntityId {
+	#[test]
+	fn despawn_fixes_up_the_swapped_row() {
+		let mut world = World::init();
+		let ids: Vec<_> = (0..4).map(|i| world.spawn((A(i),))).collect();
+		// the last row is swapped into the hole the first one leaves
+		world.despawn(ids[0]).unwrap();
+		for (i, id) in ids.iter().enumerate().skip(1) {
+			assert_eq!(world.get::<A>(*id).unwrap().unwrap().0, i as u32);
+		}
+		world.despawn(ids[3]).unwrap();
+		assert_eq!(world.get::<A>(ids[1]).unwrap().unwrap().0, 1);
+		assert_eq!(world.get::<A>(ids[2]).unwrap().unwrap().0, 2);
+		let mut left: Vec<_> = world.query::<(&A,)>().iter().map(|(id, a)| (id, a.0)).collect();
+		left.sort();
+		assert_eq!(left, vec![(ids[1], 1), (ids[2], 2)]);
+	}
+
+	#[test]
+	fn remove_component_hands_back_the_value() {
+		let mut world = World::init();
+		let id = world.spawn((A(7), B("kept")));
+		let other = world.spawn((A(8), B("other")));
+		assert_eq!(world.remove_component::<A>(id).unwrap(), Some(A(7)));
+		assert_eq!(world.remove_component::<A>(id).unwrap(), None);
+		assert!(!world.has::<A>(id).unwrap());
+		assert_eq!(world.get::<B>(id).unwrap().unwrap().0, "kept");
+		assert_eq!(world.get::<A>(other).unwrap().unwrap().0, 8);
+		assert_eq!(world.get::<B>(other).unwrap().unwrap().0, "other");
+		assert_eq!(world.remove_component::<B>(id).unwrap(), Some(B("kept")));
+		assert!(world.is_alive(id));
+	}
+
+	#[test]
+	fn archetypes_are_interned_by_sorted_signature() {
+		let mut world = World::init();
+		let bundled = world.spawn((A(1), B("bundled")));
+		let reversed = world.spawn((B("reversed"), A(2)));
+		assert_eq!(world.archetype_generation(), 2);
+		assert_eq!(world.entities.get(bundled).unwrap().archetype_id, world.entities.get(reversed).unwrap().archetype_id);
+
+		// adding the components one by one walks through the A archetype and ends up in the bundled one
+		let built = world.spawn((B("built"),));
+		world.set_component(built, A(3)).unwrap();
+		assert_eq!(world.archetype_generation(), 3);
+		assert_eq!(world.entities.get(built).unwrap().archetype_id, world.entities.get(bundled).unwrap().archetype_id);
+		let grown = world.spawn((A(4),));
+		world.set_component(grown, B("grown")).unwrap();
+		assert_eq!(world.archetype_generation(), 4);
+		assert_eq!(world.entities.get(grown).unwrap().archetype_id, world.entities.get(bundled).unwrap().archetype_id);
+	}
 
-		let swapped = *self.entity_ids.last().unwrap();
-		self.entity_ids.swap_remove(row_index);
-		for storage in self.components.values_mut() {
-			storage.swap_remove(row_index);
+	#[test]
+	fn archetype_edges_are_reused() {
+		let mut world = World::init();
+		let id = world.spawn((A(1),));
+		let from = world.entities.get(id).unwrap().archetype_id;
+		world.set_component(id, B("b")).unwrap();
+		let to = world.entities.get(id).unwrap().archetype_id;
+		assert_eq!(world.archetypes[from as usize].borrow().edges.add.get(&ComponentTypeId::of::<B>()), Some(&to));
+		assert_eq!(world.archetypes[to as usize].borrow().edges.remove.get(&ComponentTypeId::of::<B>()), Some(&from));
+
+		let generation = world.archetype_generation();
+		for _ in 0..3 {
+			world.remove_component::<B>(id).unwrap();
+			assert_eq!(world.entities.get(id).unwrap().archetype_id, from);
+			world.set_component(id, B("b")).unwrap();
+			assert_eq!(world.entities.get(id).unwrap().archetype_id, to);
 		}
-		swapped
+		assert_eq!(world.archetype_generation(), generation);
 	}
-	pub fn set<T: Component>(&mut self, row_index: usize, component: T) -> Result<T, String> {
-		let target_pointer: &mut T = self.components.get_mut(&ComponentTypeId::of::<T>()).ok_or("Set called with wrong component type for this archetype")?
-		.get_mut(row_index).ok_or("Row index out of bounds")?.downcast_mut().ok_or(format!("AnyVec in wrong row, should be of type {:?}", ComponentTypeId::of::<T>()))?;
-		
-		let old_val = replace(target_pointer, component);
 
-		Ok(old_val)
+	#[test]
+	fn sparse_components_filter_queries() {
+		struct Marker;
+		let mut world = World::init();
+		world.register_sparse::<Marker>().unwrap();
+		let ids: Vec<_> = (0..6).map(|i| world.spawn((A(i),))).collect();
+		let generation = world.archetype_generation();
+		for id in ids.iter().step_by(2) {
+			world.set_component(*id, Marker).unwrap();
+		}
+		// toggling a sparse component never moves the entity to another archetype
+		assert_eq!(world.archetype_generation(), generation);
 
+		let marked = |world: &World| {
+			let mut found: Vec<_> = world.query_filtered::<(&A,), (With<Marker>,)>().iter().map(|(_, a)| a.0).collect();
+			found.sort();
+			found
+		};
+		let unmarked = |world: &World| {
+			let mut found: Vec<_> = world.query_filtered::<(&A,), (Without<Marker>,)>().iter().map(|(_, a)| a.0).collect();
+			found.sort();
+			found
+		};
+		assert_eq!(marked(&world), vec![0, 2, 4]);
+		assert_eq!(unmarked(&world), vec![1, 3, 5]);
+
+		assert!(world.remove_component::<Marker>(ids[2]).unwrap().is_some());
+		world.set_component(ids[3], Marker).unwrap();
+		world.despawn(ids[4]).unwrap();
+		assert_eq!(marked(&world), vec![0, 3]);
+		assert_eq!(unmarked(&world), vec![1, 2, 5]);
+		assert_eq!(world.archetype_generation(), generation);
+
+		// a despawned entity must not leave its marker behind for the next one in its slot
+		let reused = world.spawn((A(6),));
+		assert_eq!(reused.index(), ids[4].index());
+		assert!(!world.has::<Marker>(reused).unwrap());
+		assert_eq!(unmarked(&world), vec![1, 2, 5, 6]);
 	}
 
-	pub fn push<T: Component>(&mut self, component: T) -> Result<(), String> {
-		self.components.get_mut(&ComponentTypeId::of::<T>()).ok_or("Set called with wrong component type for this archetype")?
-		.downcast_mut().ok_or(format!("AnyVec in wrong row, should be of type {:?}", ComponentTypeId::of::<T>()))?.push(component);
+	fn children(world: &World, parent: EntityId) -> Vec<EntityId> {
+		world.clone_component::<Children>(parent).unwrap().map(|children| children.0).unwrap_or_default()
+	}
 
-		Ok(())
-		
+	#[test]
+	fn parents_and_children_stay_in_step() {
+		let mut world = World::init();
+		let (first, second) = (world.spawn((A(1),)), world.spawn((A(2),)));
+		let child = world.spawn((A(3),));
+		world.set_parent(child, first).unwrap();
+		assert_eq!(world.get::<Parent>(child).unwrap().unwrap().get(), first);
+		assert_eq!(children(&world, first), vec![child]);
+
+		world.set_parent(child, second).unwrap();
+		assert!(!world.has::<Children>(first).unwrap());
+		assert_eq!(children(&world, second), vec![child]);
+		assert!(world.set_parent(second, child).is_err());
+		assert!(world.set_parent(child, child).is_err());
+
+		assert_eq!(world.remove_parent(child).unwrap(), Some(second));
+		assert_eq!(world.remove_parent(child).unwrap(), None);
+		assert!(!world.has::<Children>(second).unwrap());
+
+		world.set_parent(child, first).unwrap();
+		assert_eq!(world.remove_component::<Parent>(child).unwrap().map(|parent| parent.get()), Some(first));
+		assert!(!world.has::<Children>(first).unwrap());
+	}
+
+	#[test]
+	fn despawn_takes_the_whole_tree() {
+		let mut world = World::init();
+		let root = world.spawn((A(0),));
+		let branch = world.spawn((A(1),));
+		let leaves: Vec<_> = (2..5).map(|i| world.spawn((A(i),))).collect();
+		let other = world.spawn((A(5),));
+		world.set_parent(branch, root).unwrap();
+		for leaf in &leaves {
+			world.set_parent(*leaf, branch).unwrap();
+		}
+		world.set_parent(other, root).unwrap();
+
+		world.despawn(leaves[0]).unwrap();
+		assert_eq!(children(&world, branch), leaves[1..]);
+		world.despawn(branch).unwrap();
+		assert!(leaves.iter().all(|leaf| !world.is_alive(*leaf)));
+		assert_eq!(children(&world, root), vec![other]);
+		world.despawn(root).unwrap();
+		assert_eq!(world.entity_count(), 0);
+	}
 
+	#[test]
+	fn despawn_skips_children_that_are_gone() {
+		let mut world = World::init();
+		let parent = world.spawn((A(0),));
+		let kids: Vec<_> = (1..4).map(|i| world.spawn((A(i),))).collect();
+		for kid in &kids {
+			world.set_parent(*kid, parent).unwrap();
+		}
+		// through remove_component the parent's Children still has to lose the child
+		world.remove_component::<Parent>(kids[1]).unwrap();
+		world.despawn(kids[1]).unwrap();
+		assert_eq!(children(&world, parent), vec![kids[0], kids[2]]);
 
+		// a Children list that went stale some other way must not stop the despawn halfway
+		let mut stale = world.clone_component::<Children>(parent).unwrap().unwrap();
+		stale.0.insert(1, kids[1]);
+		world.set_component(parent, stale).unwrap();
+		world.despawn(parent).unwrap();
+		assert_eq!(world.entity_count(), 0);
+	}
+
+	fn prefab_world() -> World {
+		let mut world = World::init();
+		world.register_component::<Position>("position").unwrap();
+		world.register_component::<Velocity>("velocity").unwrap();
+		world.register_component::<Collider>("collider").unwrap();
+		world.register_component::<Friction>("friction").unwrap();
+		world.register_component::<MaxSpeed>("max_speed").unwrap();
+		world.insert_resource(PrefabManager::new(&PrefabLoader));
+		world
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn prefabs_spawn_straight_into_their_archetype() {
+		let mut world = prefab_world();
+		let zombie = world.spawn_prefab("zombie").unwrap();
+		world.spawn_prefab("zombie").unwrap();
+		// the empty archetype and the zombie's, nothing in between
+		assert_eq!(world.archetype_generation(), 2);
+		assert_eq!(world.get::<Position>(zombie).unwrap().unwrap().0.x, 192.0);
+		assert_eq!(world.get::<Friction>(zombie).unwrap().unwrap().0, 4.0);
+
+		let overrides = json!({ "position": [1.0, 2.0], "friction": 0.5 });
+		let moved = world.spawn_prefab_with("zombie", overrides.as_object().unwrap()).unwrap();
+		assert_eq!(*world.get::<Position>(moved).unwrap().unwrap(), Position((1.0, 2.0).into()));
+		assert_eq!(world.get::<Friction>(moved).unwrap().unwrap().0, 0.5);
+		assert_eq!(world.archetype_generation(), 2);
+	}
+
+	#[test]
+	fn failed_prefabs_spawn_nothing() {
+		let mut world = prefab_world();
+		let mut overrides = Map::new();
+		overrides.insert("position".to_string(), json!("not a position"));
+		assert!(world.spawn_prefab_with("zombie", &overrides).is_err());
+		overrides.clear();
+		overrides.insert("unregistered".to_string(), json!(1));
+		assert!(world.spawn_prefab_with("zombie", &overrides).is_err());
+		assert!(world.spawn_prefab("no such prefab").is_err());
+		assert_eq!(world.entity_count(), 0);
+		assert_eq!(world.archetype_generation(), 1);
+	}
+}
@@ -0,0 +1,62 @@
+use std::{collections::HashMap, sync::Arc};
+
+use super::{commands::{CommandQueue, Commands}, components::ComponentTypeId, EntityId, World};
+
+/* What happened to a component of an entity. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Lifecycle {
+    /* The entity got the component, by spawning or set_component. Observers see the new value. */
+    Add,
+    /* set_component overwrote a value the entity already had. Observers see the new value. */
+    Replace,
+    /* The component is about to be removed, or the entity is about to be despawned. Observers still see the value. */
+    Remove,
+}
+
+/* Called right where the change happens, with the World shared. Structural changes go through the commands,
+ * which are applied as soon as the change that triggered the observer is done.
+ */
+pub type Observer = Arc<dyn Fn(&World, EntityId, &mut Commands) + Send + Sync>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(u32);
+
+#[derive(Default)]
+pub struct Observers {
+    observers: HashMap<(ComponentTypeId, Lifecycle), Vec<(ObserverId, Observer)>>,
+    next_id: u32,
+}
+
+impl Observers {
+    pub fn add(&mut self, component: ComponentTypeId, event: Lifecycle, observer: Observer) -> ObserverId {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.entry((component, event)).or_default().push((id, observer));
+        id
+    }
+    pub fn remove(&mut self, id: ObserverId) -> bool {
+        let before = self.len();
+        self.observers.retain(|_, observers| {
+            observers.retain(|(other, _)| *other != id);
+            !observers.is_empty()
+        });
+        self.len() != before
+    }
+    pub fn len(&self) -> usize {
+        self.observers.values().map(Vec::len).sum()
+    }
+    /* Checked before every structural change, so keys without observers are never kept around. */
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    /* Runs the observers of every listed component in registration order, recording their commands in queue. */
+    pub(super) fn trigger(&self, world: &World, event: Lifecycle, components: &[ComponentTypeId], id: EntityId, queue: &mut CommandQueue) {
+        let mut commands = Commands::new(world, queue);
+        for component in components {
+            for (_, observer) in self.observers.get(&(*component, event)).into_iter().flatten() {
+                observer(world, id, &mut commands);
+            }
+        }
+    }
+}
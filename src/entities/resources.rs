@@ -0,0 +1,108 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{RwLockReadGuard, RwLockWriteGuard},
+};
+
+use super::cell::SyncCell;
+
+/* World wide singletons like the loaded Map or the input state, at most one per type. */
+pub trait Resource: 'static + Send + Sync {}
+
+impl<T: 'static + Send + Sync> Resource for T {}
+
+type BoxedResource = Box<dyn Any + Send + Sync>;
+
+#[derive(Default)]
+pub struct Resources {
+    resources: HashMap<TypeId, SyncCell<BoxedResource>>,
+    /* Resources that opted into snapshots, with how to clone them. */
+    cloners: HashMap<TypeId, fn(&BoxedResource) -> BoxedResource>,
+}
+
+impl Resources {
+    pub fn insert<R: Resource>(&mut self, resource: R) -> Option<R> {
+        self.resources
+            .insert(TypeId::of::<R>(), SyncCell::new(Box::new(resource)))
+            .map(|old| *old.into_inner().downcast::<R>().expect("resource stored under the wrong type"))
+    }
+    pub fn remove<R: Resource>(&mut self) -> Option<R> {
+        self.resources
+            .remove(&TypeId::of::<R>())
+            .map(|old| *old.into_inner().downcast::<R>().expect("resource stored under the wrong type"))
+    }
+    pub fn contains<R: Resource>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<R>())
+    }
+    pub fn get<R: Resource>(&self) -> Option<Res<'_, R>> {
+        let cell = self.resources.get(&TypeId::of::<R>())?;
+        Some(Res {
+            guard: cell.borrow(),
+            marker: PhantomData,
+        })
+    }
+    pub fn register_clone<R: Resource + Clone>(&mut self) {
+        self.cloners.insert(TypeId::of::<R>(), |resource| {
+            Box::new(resource.downcast_ref::<R>().expect("resource stored under the wrong type").clone())
+        });
+    }
+    /* Clones of every registered resource currently present. */
+    pub(super) fn clone_registered(&self) -> Vec<(TypeId, BoxedResource)> {
+        self.cloners
+            .iter()
+            .filter_map(|(id, clone)| Some((*id, clone(&self.resources.get(id)?.borrow()))))
+            .collect()
+    }
+    /* Puts back the registered resources as they were in clones, removing the ones that were not there. */
+    pub(super) fn restore_registered(&mut self, clones: &[(TypeId, BoxedResource)]) {
+        for (id, clone) in &self.cloners {
+            match clones.iter().find(|(other, _)| other == id) {
+                Some((_, resource)) => self.resources.insert(*id, SyncCell::new(clone(resource))),
+                None => self.resources.remove(id),
+            };
+        }
+    }
+    pub fn get_mut<R: Resource>(&self) -> Option<ResMut<'_, R>> {
+        let cell = self.resources.get(&TypeId::of::<R>())?;
+        Some(ResMut {
+            guard: cell.borrow_mut(),
+            marker: PhantomData,
+        })
+    }
+}
+
+/* Shared borrow of a resource, released when dropped. */
+pub struct Res<'w, R: Resource> {
+    guard: RwLockReadGuard<'w, BoxedResource>,
+    marker: PhantomData<&'w R>,
+}
+
+impl<'w, R: Resource> Deref for Res<'w, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.guard.downcast_ref::<R>().expect("resource stored under the wrong type")
+    }
+}
+
+/* Exclusive borrow of a resource, released when dropped. */
+pub struct ResMut<'w, R: Resource> {
+    guard: RwLockWriteGuard<'w, BoxedResource>,
+    marker: PhantomData<&'w mut R>,
+}
+
+impl<'w, R: Resource> Deref for ResMut<'w, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.guard.downcast_ref::<R>().expect("resource stored under the wrong type")
+    }
+}
+
+impl<'w, R: Resource> DerefMut for ResMut<'w, R> {
+    fn deref_mut(&mut self) -> &mut R {
+        self.guard.downcast_mut::<R>().expect("resource stored under the wrong type")
+    }
+}
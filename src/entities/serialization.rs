@@ -0,0 +1,538 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{Read, Write},
+    sync::PoisonError,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use super::{archetype::{Column, ComponentTicks}, components::{Component, ComponentTypeId}, EntityAllocator, EntityId, World};
+
+/* How a registered component is written to a save and read back from one. */
+#[derive(Clone, Copy)]
+pub struct ComponentRegistration {
+    name: &'static str,
+    component: ComponentTypeId,
+    serialize: fn(&Column, usize) -> Result<Value, String>,
+    deserialize: fn(Value) -> Result<Column, String>,
+    insert: fn(&mut World, EntityId, Value) -> Result<(), String>,
+}
+
+impl ComponentRegistration {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn component(&self) -> ComponentTypeId {
+        self.component
+    }
+    /* Deserializes value into a column holding just that component, for World::spawn_columns. */
+    pub fn deserialize(&self, value: Value) -> Result<Column, String> {
+        (self.deserialize)(value)
+    }
+    /* Deserializes value into the component and sets it on the entity. */
+    pub fn insert(&self, world: &mut World, id: EntityId, value: Value) -> Result<(), String> {
+        (self.insert)(world, id, value)
+    }
+}
+
+/* Components that opted into saving, under a name that stays the same across builds unlike their TypeId,
+ * components that opted into showing their Debug output in the inspector and ones that can be snapshotted.
+ */
+#[derive(Default)]
+pub struct ComponentRegistry {
+    registrations: HashMap<ComponentTypeId, ComponentRegistration>,
+    names: HashMap<&'static str, ComponentTypeId>,
+    debug: HashMap<ComponentTypeId, DebugFn>,
+    clones: HashMap<ComponentTypeId, fn(&Column) -> Column>,
+}
+
+type DebugFn = fn(&Column, usize) -> Option<String>;
+
+impl ComponentRegistry {
+    pub fn register<T: Component + Serialize + DeserializeOwned>(&mut self, name: &'static str) -> Result<(), String> {
+        let component = ComponentTypeId::of::<T>();
+        if let Some(registration) = self.registrations.get(&component) {
+            return Err(format!("{} is already registered as {}", component, registration.name));
+        }
+        if let Some(other) = self.names.get(name) {
+            return Err(format!("{} is already the name of {}", name, other));
+        }
+        self.names.insert(name, component);
+        self.registrations.insert(component, ComponentRegistration {
+            name,
+            component,
+            serialize: serialize_component::<T>,
+            deserialize: deserialize_component::<T>,
+            insert: insert_component::<T>,
+        });
+        Ok(())
+    }
+    pub fn register_debug<T: Component + Debug>(&mut self) {
+        self.debug.insert(ComponentTypeId::of::<T>(), debug_component::<T>);
+    }
+    pub fn register_clone<T: Component + Clone>(&mut self) {
+        self.clones.insert(ComponentTypeId::of::<T>(), clone_column::<T>);
+    }
+    /* Copy of every row of the column, ticks included. None if the component did not register for cloning. */
+    pub fn clone_column(&self, component: &ComponentTypeId, column: &Column) -> Option<Column> {
+        Some(self.clones.get(component)?(column))
+    }
+    /* Debug output of the component in that row of its column, its saved form if it only registered for saving. */
+    pub fn describe(&self, component: &ComponentTypeId, column: &Column, row: usize) -> Option<String> {
+        if let Some(debug) = self.debug.get(component) {
+            return debug(column, row);
+        }
+        let registration = self.registrations.get(component)?;
+        (registration.serialize)(column, row).ok().map(|value| value.to_string())
+    }
+    pub fn get(&self, component: &ComponentTypeId) -> Option<&ComponentRegistration> {
+        self.registrations.get(component)
+    }
+    pub fn get_by_name(&self, name: &str) -> Option<&ComponentRegistration> {
+        self.names.get(name).and_then(|component| self.registrations.get(component))
+    }
+}
+
+fn serialize_component<T: Component + Serialize>(column: &Column, row: usize) -> Result<Value, String> {
+    let component = column
+        .data
+        .downcast_ref::<T>()
+        .and_then(|data| data.get(row))
+        .ok_or(format!("no {} in row {}", ComponentTypeId::of::<T>(), row))?;
+    serde_json::to_value(component).map_err(|e| e.to_string())
+}
+
+fn debug_component<T: Component + Debug>(column: &Column, row: usize) -> Option<String> {
+    Some(format!("{:?}", column.data.downcast_ref::<T>()?.get(row)?))
+}
+
+fn clone_column<T: Component + Clone>(column: &Column) -> Column {
+    let mut clone = column.clone_empty();
+    {
+        let mut data = clone.data.downcast_mut::<T>().expect("column stored under the wrong type");
+        for component in column.data.downcast_ref::<T>().expect("column stored under the wrong type").as_slice() {
+            data.push(component.clone());
+        }
+    }
+    clone.ticks = column.ticks.clone();
+    clone
+}
+
+fn deserialize_component<T: Component + DeserializeOwned>(value: Value) -> Result<Column, String> {
+    let component: T = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    let mut column = Column::new::<T>();
+    column.data.downcast_mut::<T>().expect("new column of the wrong type").push(component);
+    column.ticks.push(ComponentTicks::new(0));
+    Ok(column)
+}
+
+fn insert_component<T: Component + DeserializeOwned>(world: &mut World, id: EntityId, value: Value) -> Result<(), String> {
+    let component: T = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    world.set_component(id, component).map(|_| ())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SaveFormat {
+    Json,
+    /* Same document as Json, encoded by encode_value. */
+    Binary,
+}
+
+/* The allocator is saved as well so ids stored inside components still point at the right entity after loading. */
+#[derive(Serialize, Deserialize)]
+struct SavedWorld {
+    generations: Vec<u32>,
+    free_indices: Vec<u32>,
+    entities: Vec<SavedEntity>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntity {
+    id: EntityId,
+    components: Map<String, Value>,
+}
+
+/* Components nobody registered are left out of the save. */
+pub(super) fn save<W: Write>(world: &World, mut writer: W, format: SaveFormat) -> Result<(), String> {
+    let mut entities = Vec::new();
+    for archetype in &world.archetypes {
+        let archetype = archetype.borrow();
+        let registered: Vec<(&ComponentRegistration, &Column)> = archetype
+            .signature
+            .iter()
+            .filter_map(|component| Some((world.registry.get(component)?, &archetype.components[component])))
+            .collect();
+        for (row, id) in archetype.entity_ids.iter().enumerate() {
+            let mut components = Map::new();
+            for (registration, column) in &registered {
+                components.insert(registration.name.to_string(), (registration.serialize)(column, row)?);
+            }
+            entities.push(SavedEntity { id: *id, components });
+        }
+    }
+    entities.sort_by_key(|entity| entity.id);
+    for (component, set) in &world.sparse_sets {
+        let registration = match world.registry.get(component) {
+            Some(registration) => registration,
+            None => continue,
+        };
+        let set = set.borrow();
+        for (row, id) in set.entity_ids.iter().enumerate() {
+            let entity = entities.binary_search_by_key(id, |entity| entity.id).map_err(|_| format!("{:?} is in a sparse set but not alive", id))?;
+            entities[entity].components.insert(registration.name.to_string(), (registration.serialize)(&set.column, row)?);
+        }
+    }
+    let allocator = world.allocator.lock().unwrap_or_else(PoisonError::into_inner);
+    let saved = SavedWorld {
+        generations: allocator.generations.clone(),
+        free_indices: allocator.free_indices.clone(),
+        entities,
+    };
+    drop(allocator);
+    match format {
+        SaveFormat::Json => serde_json::to_writer(writer, &saved).map_err(|e| e.to_string()),
+        SaveFormat::Binary => {
+            let mut bytes = MAGIC.to_vec();
+            encode_value(&serde_json::to_value(&saved).map_err(|e| e.to_string())?, &mut bytes);
+            writer.write_all(&bytes).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/* Replaces every entity of the world with the saved ones, resources and the registry are kept.
+ * The whole save is checked and deserialized first, a save that fails to load leaves the world untouched.
+ */
+pub(super) fn load<R: Read>(world: &mut World, mut reader: R, format: SaveFormat) -> Result<(), String> {
+    let saved: SavedWorld = match format {
+        SaveFormat::Json => serde_json::from_reader(reader).map_err(|e| e.to_string())?,
+        SaveFormat::Binary => {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+            let mut input = bytes.strip_prefix(MAGIC).ok_or("not a binary world save")?;
+            let value = decode_value(&mut input)?;
+            if !input.is_empty() {
+                return Err("trailing bytes after the world save".to_string());
+            }
+            serde_json::from_value(value).map_err(|e| e.to_string())?
+        }
+    };
+    // a bad free list would have the allocator hand out indices it has no generation for, or the same id twice
+    let mut free = vec![false; saved.generations.len()];
+    for index in &saved.free_indices {
+        match free.get_mut(*index as usize) {
+            None => return Err(format!("free index {} is past the saved allocator", index)),
+            Some(true) => return Err(format!("free index {} appears more than once", index)),
+            Some(free) => *free = true,
+        }
+    }
+    let mut seen = vec![false; saved.generations.len()];
+    for entity in &saved.entities {
+        let index = entity.id.index as usize;
+        if saved.generations.get(index) != Some(&entity.id.generation) || free[index] {
+            return Err(format!("saved entity {:?} does not match the saved allocator", entity.id));
+        }
+        if std::mem::replace(&mut seen[index], true) {
+            return Err(format!("saved entity {:?} appears more than once", entity.id));
+        }
+    }
+    let mut entities = Vec::with_capacity(saved.entities.len());
+    for entity in saved.entities {
+        let mut components = Vec::with_capacity(entity.components.len());
+        for (name, value) in entity.components {
+            let registration = world.registry.get_by_name(&name).ok_or(format!("no component is registered as {}", name))?;
+            let column = registration.deserialize(value).map_err(|e| format!("{} of {:?}: {}", name, entity.id, e))?;
+            components.push((registration.component, column));
+        }
+        entities.push((entity.id, components));
+    }
+
+    world.clear_entities();
+    *world.allocator.get_mut().unwrap_or_else(PoisonError::into_inner) = EntityAllocator {
+        generations: saved.generations,
+        free_indices: saved.free_indices,
+    };
+    for (id, components) in entities {
+        world.spawn_columns(id, components);
+    }
+    Ok(())
+}
+
+const MAGIC: &[u8] = b"ECS\x01";
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const UNSIGNED: u8 = 3;
+const NEGATIVE: u8 = 4;
+const FLOAT32: u8 = 5;
+const FLOAT64: u8 = 6;
+const STRING: u8 = 7;
+const ARRAY: u8 = 8;
+const OBJECT: u8 = 9;
+
+/* One tag byte per value, integers and lengths as LEB128 varints, floats that fit an f32 as one. */
+fn encode_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(NULL),
+        Value::Bool(false) => out.push(FALSE),
+        Value::Bool(true) => out.push(TRUE),
+        Value::Number(number) => {
+            if let Some(unsigned) = number.as_u64() {
+                out.push(UNSIGNED);
+                encode_varint(unsigned, out);
+            } else if let Some(signed) = number.as_i64() {
+                out.push(NEGATIVE);
+                encode_varint(!(signed as u64), out);
+            } else {
+                let float = number.as_f64().unwrap_or(0.0);
+                if (float as f32) as f64 == float {
+                    out.push(FLOAT32);
+                    out.extend_from_slice(&(float as f32).to_le_bytes());
+                } else {
+                    out.push(FLOAT64);
+                    out.extend_from_slice(&float.to_le_bytes());
+                }
+            }
+        }
+        Value::String(string) => {
+            out.push(STRING);
+            encode_str(string, out);
+        }
+        Value::Array(values) => {
+            out.push(ARRAY);
+            encode_varint(values.len() as u64, out);
+            for value in values {
+                encode_value(value, out);
+            }
+        }
+        Value::Object(map) => {
+            out.push(OBJECT);
+            encode_varint(map.len() as u64, out);
+            for (key, value) in map {
+                encode_str(key, out);
+                encode_value(value, out);
+            }
+        }
+    }
+}
+
+fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn encode_str(string: &str, out: &mut Vec<u8>) {
+    encode_varint(string.len() as u64, out);
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if input.len() < len {
+        return Err("world save ends early".to_string());
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    Ok(taken)
+}
+
+fn decode_value(input: &mut &[u8]) -> Result<Value, String> {
+    let tag = take(input, 1)?[0];
+    Ok(match tag {
+        NULL => Value::Null,
+        FALSE => Value::Bool(false),
+        TRUE => Value::Bool(true),
+        UNSIGNED => Value::Number(decode_varint(input)?.into()),
+        NEGATIVE => Value::Number((!decode_varint(input)? as i64).into()),
+        FLOAT32 => {
+            let float = f32::from_le_bytes(take(input, 4)?.try_into().unwrap());
+            Value::Number(Number::from_f64(float as f64).ok_or("saved float is not finite")?)
+        }
+        FLOAT64 => {
+            let float = f64::from_le_bytes(take(input, 8)?.try_into().unwrap());
+            Value::Number(Number::from_f64(float).ok_or("saved float is not finite")?)
+        }
+        STRING => Value::String(decode_string(input)?),
+        ARRAY => {
+            let len = decode_varint(input)? as usize;
+            let mut values = Vec::with_capacity(len.min(input.len()));
+            for _ in 0..len {
+                values.push(decode_value(input)?);
+            }
+            Value::Array(values)
+        }
+        OBJECT => {
+            let len = decode_varint(input)?;
+            let mut map = Map::new();
+            for _ in 0..len {
+                let key = decode_string(input)?;
+                map.insert(key, decode_value(input)?);
+            }
+            Value::Object(map)
+        }
+        _ => return Err(format!("unknown value tag {} in world save", tag)),
+    })
+}
+
+fn decode_varint(input: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(input, 1)?[0];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint in world save is too long".to_string())
+}
+
+fn decode_string(input: &mut &[u8]) -> Result<String, String> {
+    let len = decode_varint(input)? as usize;
+    String::from_utf8(take(input, len)?.to_vec()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::{decode_value, encode_value, SaveFormat};
+    use crate::entities::{EntityId, World};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Stats {
+        level: i64,
+        precise: f64,
+        rounded: f32,
+        name: String,
+        tags: Vec<Option<bool>>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Marker;
+
+    fn world() -> World {
+        let mut world = World::init();
+        world.register_component::<Stats>("stats").unwrap();
+        world.register_component::<Marker>("marker").unwrap();
+        world.register_sparse::<Marker>().unwrap();
+        world
+    }
+
+    fn stats(level: i64) -> Stats {
+        Stats {
+            level,
+            precise: 0.1,
+            rounded: 1.5,
+            name: "zombie".to_string(),
+            tags: vec![Some(true), None, Some(false)],
+        }
+    }
+
+    fn round_trip(format: SaveFormat) {
+        let mut world = world();
+        let gone = world.spawn((stats(1),));
+        let a = world.spawn((stats(-300),));
+        let b = world.spawn((stats(i64::MIN), Marker));
+        world.despawn(gone).unwrap();
+        let mut bytes = Vec::new();
+        world.save(&mut bytes, format).unwrap();
+
+        let mut loaded = self::world();
+        loaded.spawn((stats(7),));
+        loaded.load(bytes.as_slice(), format).unwrap();
+        assert_eq!(loaded.entity_count(), 2);
+        assert!(!loaded.is_alive(gone));
+        assert_eq!(*loaded.get::<Stats>(a).unwrap().unwrap(), stats(-300));
+        assert_eq!(*loaded.get::<Stats>(b).unwrap().unwrap(), stats(i64::MIN));
+        assert!(loaded.has::<Marker>(b).unwrap() && !loaded.has::<Marker>(a).unwrap());
+        // the allocator came along, so the despawned index is reused with its bumped generation
+        let reused = loaded.spawn(());
+        assert_eq!(reused.index(), gone.index());
+        assert_ne!(reused, gone);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(SaveFormat::Json);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        round_trip(SaveFormat::Binary);
+    }
+
+    #[test]
+    fn binary_values() {
+        let value = json!({
+            "unsigned": [0, 127, 128, u64::MAX],
+            "negative": [-1, -128, i64::MIN],
+            "f32": [1.5, -0.25],
+            "f64": [0.1, 1e300, -2.5e-10],
+            "other": [null, true, false, "", "text"],
+        });
+        let mut bytes = Vec::new();
+        encode_value(&value, &mut bytes);
+        let mut input = bytes.as_slice();
+        assert_eq!(decode_value(&mut input).unwrap(), value);
+        assert!(input.is_empty());
+        let mut truncated = &bytes[..bytes.len() - 1];
+        assert!(decode_value(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let save = json!({
+            "generations": [0],
+            "free_indices": [],
+            "entities": [
+                {"id": {"index": 0, "generation": 0}, "components": {}},
+                {"id": {"index": 0, "generation": 0}, "components": {}},
+            ],
+        });
+        let mut world = world();
+        let kept = world.spawn((stats(3),));
+        assert!(world.load(save.to_string().as_bytes(), SaveFormat::Json).is_err());
+        assert_eq!(world.entity_count(), 1);
+        assert_eq!(world.query::<(&Stats,)>().iter().count(), 1);
+        assert!(world.is_alive(kept));
+    }
+
+    #[test]
+    fn bad_free_indices_are_rejected() {
+        let save = |free_indices| json!({"generations": [0, 0], "free_indices": free_indices, "entities": []}).to_string();
+        let mut world = world();
+        let kept = world.spawn((stats(3),));
+        assert!(world.load(save(json!([5])).as_bytes(), SaveFormat::Json).is_err());
+        assert!(world.load(save(json!([2])).as_bytes(), SaveFormat::Json).is_err());
+        assert!(world.load(save(json!([1, 1])).as_bytes(), SaveFormat::Json).is_err());
+        assert_eq!(world.entity_count(), 1);
+        assert!(world.is_alive(kept));
+
+        world.load(save(json!([1, 0])).as_bytes(), SaveFormat::Json).unwrap();
+        let first = world.spawn((stats(1),));
+        let second = world.spawn((stats(2),));
+        assert_ne!(first, second);
+        assert!(first.index() < 2 && second.index() < 2);
+        assert_eq!(world.get::<Stats>(first).unwrap().unwrap().level, 1);
+        assert_eq!(world.get::<Stats>(second).unwrap().unwrap().level, 2);
+    }
+
+    #[test]
+    fn bad_components_leave_the_world_untouched() {
+        let entity = |components| json!({"id": {"index": 0, "generation": 0}, "components": components});
+        let save = |components| json!({"generations": [0], "free_indices": [], "entities": [entity(components)]}).to_string();
+        let mut world = world();
+        let kept = world.spawn((stats(3),));
+        assert!(world.load(save(json!({"stats": stats(1), "unknown": 1})).as_bytes(), SaveFormat::Json).is_err());
+        assert!(world.load(save(json!({"stats": {"level": "not a number"}})).as_bytes(), SaveFormat::Json).is_err());
+        assert_eq!(world.entity_count(), 1);
+        assert_eq!(*world.get::<Stats>(kept).unwrap().unwrap(), stats(3));
+        world.load(save(json!({"stats": stats(1)})).as_bytes(), SaveFormat::Json).unwrap();
+        assert_eq!(*world.get::<Stats>(EntityId { index: 0, generation: 0 }).unwrap().unwrap(), stats(1));
+    }
+}
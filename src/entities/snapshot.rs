@@ -0,0 +1,281 @@
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, VecDeque},
+    sync::PoisonError,
+};
+
+use super::{
+    cell::SyncCell,
+    components::ComponentTypeId,
+    sparse_set::SparseSet,
+    systems::{schedule::Schedule, SavedState},
+    ArchetypeId, Column, EntityAllocator, EntityId, EntityTable, World,
+};
+
+/* Copy of everything a World can be rolled back to: every entity with its components, the entity allocator,
+ * the ticks and the resources registered with World::register_clone_resource. Other resources are left alone.
+ * Every component an entity has must have been registered with World::register_clone.
+ */
+pub struct Snapshot {
+    change_tick: u32,
+    last_run: u32,
+    entities: EntityTable,
+    allocator: EntityAllocator,
+    /* Rows of every archetype that had any. */
+    archetypes: Vec<(ArchetypeId, Vec<EntityId>, HashMap<ComponentTypeId, Column>)>,
+    sparse_sets: Vec<(ComponentTypeId, SparseSet)>,
+    resources: Vec<(TypeId, Box<dyn Any + Send + Sync>)>,
+}
+
+impl Snapshot {
+    pub fn change_tick(&self) -> u32 {
+        self.change_tick
+    }
+    pub fn entity_count(&self) -> usize {
+        self.entities.len()
+    }
+}
+
+pub(super) fn take(world: &World) -> Result<Snapshot, String> {
+    let clone = |component: &ComponentTypeId, column: &Column| {
+        world.registry.clone_column(component, column).ok_or(format!("{} is not registered for snapshots", component))
+    };
+    let mut archetypes = Vec::new();
+    for (id, archetype) in world.archetypes.iter().enumerate() {
+        let archetype = archetype.borrow();
+        if archetype.entity_ids.is_empty() {
+            continue;
+        }
+        let columns = archetype
+            .components
+            .iter()
+            .map(|(component, column)| Ok((*component, clone(component, column)?)))
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        archetypes.push((id as ArchetypeId, archetype.entity_ids.clone(), columns));
+    }
+    let mut sparse_sets = Vec::new();
+    for (component, set) in &world.sparse_sets {
+        let set = set.borrow();
+        if !set.is_empty() {
+            sparse_sets.push((*component, set.clone_with(clone(component, &set.column)?)));
+        }
+    }
+    Ok(Snapshot {
+        change_tick: world.change_tick,
+        last_run: world.last_run,
+        entities: world.entities.clone(),
+        allocator: world.allocator.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+        archetypes,
+        sparse_sets,
+        resources: world.resources.clone_registered(),
+    })
+}
+
+/* Archetypes created since the snapshot was taken stay around, empty. The snapshot can be restored again. */
+pub(super) fn restore(world: &mut World, snapshot: &Snapshot) {
+    // registrations are never removed, so whatever was cloned into the snapshot can be cloned back out
+    let clone = |world: &World, component: &ComponentTypeId, column: &Column| {
+        world.registry.clone_column(component, column).expect("snapshotted component lost its registration")
+    };
+    for archetype in world.archetypes.iter_mut() {
+        archetype.get_mut().clear();
+    }
+    for (id, entity_ids, columns) in &snapshot.archetypes {
+        let columns: Vec<(ComponentTypeId, Column)> = columns.iter().map(|(component, column)| (*component, clone(world, component, column))).collect();
+        let archetype = world.archetypes[*id as usize].get_mut();
+        archetype.entity_ids = entity_ids.clone();
+        archetype.components.extend(columns);
+    }
+    for set in world.sparse_sets.values_mut() {
+        set.get_mut().clear();
+    }
+    for (component, set) in &snapshot.sparse_sets {
+        let restored = set.clone_with(clone(world, component, &set.column));
+        world.sparse_sets.insert(*component, SyncCell::new(restored));
+    }
+    world.entities = snapshot.entities.clone();
+    *world.allocator.get_mut().unwrap_or_else(PoisonError::into_inner) = snapshot.allocator.clone();
+    world.change_tick = snapshot.change_tick;
+    world.last_run = snapshot.last_run;
+    world.resources.restore_registered(&snapshot.resources);
+}
+
+/* The last few snapshots of a World, the oldest dropped first. Each is kept with the last run ticks and the
+ * saved states of the schedule's systems, so after a rewind the systems see exactly the changes and events
+ * they saw the first time around.
+ */
+pub struct SnapshotRing {
+    capacity: usize,
+    snapshots: VecDeque<Recorded>,
+}
+
+struct Recorded {
+    snapshot: Snapshot,
+    system_ticks: Vec<u32>,
+    system_states: Vec<Option<SavedState>>,
+}
+
+impl SnapshotRing {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a snapshot ring needs room for at least one snapshot");
+        SnapshotRing {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /* Meant to be called between schedule runs. Returns the change tick to rewind to this snapshot with. */
+    pub fn record(&mut self, world: &World, schedule: &Schedule) -> Result<u32, String> {
+        let snapshot = world.snapshot()?;
+        let tick = snapshot.change_tick();
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Recorded {
+            snapshot,
+            system_ticks: schedule.system_ticks(),
+            system_states: schedule.system_states(),
+        });
+        Ok(tick)
+    }
+
+    /* Restores the snapshot recorded at that tick. The ones recorded after it are dropped, they belong to
+     * the timeline being replayed and would not match what the replay records.
+     */
+    pub fn rewind(&mut self, tick: u32, world: &mut World, schedule: &mut Schedule) -> Result<(), String> {
+        let index = self
+            .snapshots
+            .iter()
+            .position(|recorded| recorded.snapshot.change_tick() == tick)
+            .ok_or(format!("no snapshot was recorded at tick {}", tick))?;
+        let recorded = &self.snapshots[index];
+        schedule.set_system_ticks(&recorded.system_ticks)?;
+        schedule.set_system_states(&recorded.system_states)?;
+        world.restore(&recorded.snapshot);
+        self.snapshots.truncate(index + 1);
+        Ok(())
+    }
+
+    /* Ticks of the snapshots held, oldest first. */
+    pub fn ticks(&self) -> impl Iterator<Item = u32> + '_ {
+        self.snapshots.iter().map(|recorded| recorded.snapshot.change_tick())
+    }
+    pub fn latest(&self) -> Option<&Snapshot> {
+        self.snapshots.back().map(|recorded| &recorded.snapshot)
+    }
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::entities::{
+        commands::Commands,
+        events::{EventCursor, Events},
+        serialization::SaveFormat,
+        systems::{
+            schedule::{Schedule, Stage},
+            stateful,
+        },
+        EntityId, World,
+    };
+
+    use super::SnapshotRing;
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct Height(i32);
+    #[derive(Clone, Serialize, Deserialize)]
+    struct Speed(i32);
+    #[derive(Clone, Default)]
+    struct Bounces(u32);
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Landed(EntityId);
+
+    fn world() -> World {
+        let mut world = World::init();
+        world.register_component::<Height>("height").unwrap();
+        world.register_component::<Speed>("speed").unwrap();
+        world.register_clone::<Height>();
+        world.register_clone::<Speed>();
+        world.add_event::<Landed>();
+        world.register_clone_resource::<Events<Landed>>();
+        world.insert_resource(Bounces(0));
+        world.register_clone_resource::<Bounces>();
+        world.spawn((Height(10), Speed(3)));
+        world.spawn((Height(4), Speed(1)));
+        world
+    }
+
+    /* Falling entities send Landed when they reach the ground, every third landing spawns another one. */
+    fn schedule() -> Schedule {
+        let mut schedule = Schedule::new();
+        schedule.add_system(Stage::Update, "fall", |world: &World, _commands: &mut Commands| {
+            let mut landed = world.event_writer::<Landed>();
+            for (id, mut height, speed) in world.query::<(&mut Height, &Speed)>().iter_mut() {
+                height.0 -= speed.0;
+                if height.0 <= 0 {
+                    height.0 = 12;
+                    landed.send(Landed(id));
+                }
+            }
+        });
+        schedule
+            .add_system(
+                Stage::Update,
+                "bounce",
+                stateful(EventCursor::<Landed>::default(), |cursor, world: &World, commands: &mut Commands| {
+                    let mut bounces = world.resource_mut::<Bounces>().unwrap();
+                    for _ in world.event_reader(cursor).read() {
+                        bounces.0 += 1;
+                        if bounces.0.is_multiple_of(3) {
+                            commands.spawn((Height(bounces.0 as i32), Speed(2)));
+                        }
+                    }
+                }),
+            )
+            .after("fall");
+        schedule
+    }
+
+    fn save(world: &World) -> Vec<u8> {
+        let mut out = Vec::new();
+        world.save(&mut out, SaveFormat::Json).unwrap();
+        out
+    }
+
+    #[test]
+    fn replays_match_the_recorded_run() {
+        let mut world = world();
+        let mut schedule = schedule();
+        let mut ring = SnapshotRing::new(8);
+        let mut cursor = EventCursor::<Landed>::default();
+        let mut ticks = Vec::new();
+        let mut cursors = Vec::new();
+        let mut recorded = Vec::new();
+        for _ in 0..16 {
+            ticks.push(ring.record(&world, &schedule).unwrap());
+            cursors.push(cursor.clone());
+            world.run_schedule(&mut schedule);
+            let events: Vec<Landed> = world.event_reader(&mut cursor).read().copied().collect();
+            recorded.push((save(&world), events, world.resource::<Bounces>().unwrap().0));
+        }
+        assert!(world.entity_count() > 2, "nothing was spawned, the replay would not show much");
+
+        assert!(ring.rewind(ticks[2], &mut world, &mut schedule).is_err());
+        ring.rewind(ticks[10], &mut world, &mut schedule).unwrap();
+        cursor = cursors[10].clone();
+        assert_eq!(save(&world), recorded[9].0);
+        for expected in &recorded[10..] {
+            world.run_schedule(&mut schedule);
+            let events: Vec<Landed> = world.event_reader(&mut cursor).read().copied().collect();
+            let replayed = (save(&world), events, world.resource::<Bounces>().unwrap().0);
+            assert!(replayed == *expected, "replay went a different way at tick {}", world.change_tick());
+        }
+    }
+}
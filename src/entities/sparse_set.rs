@@ -0,0 +1,147 @@
+use std::{mem::replace, sync::RwLockReadGuard};
+
+use super::{archetype::{Column, ComponentTicks}, components::{Component, ComponentTypeId}, EntityId};
+
+/* Storage for one component type that lives outside the archetypes, opted into with World::register_sparse.
+ * Adding or removing the component never moves the entity to another archetype, which suits markers that are
+ * toggled all the time. Values are packed in a Column, sparse maps an entity index to its row in there.
+ */
+pub struct SparseSet {
+    pub(crate) column: Column,
+    pub(crate) entity_ids: Vec<EntityId>,
+    sparse: Vec<Option<usize>>,
+}
+
+impl SparseSet {
+    pub fn new<T: Component>() -> Self {
+        SparseSet {
+            column: Column::new::<T>(),
+            entity_ids: Vec::new(),
+            sparse: Vec::new(),
+        }
+    }
+
+    pub fn row(&self, id: EntityId) -> Option<usize> {
+        let row = (*self.sparse.get(id.index() as usize)?)?;
+        (self.entity_ids[row] == id).then_some(row)
+    }
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.row(id).is_some()
+    }
+    pub fn ticks(&self, id: EntityId) -> Option<&ComponentTicks> {
+        self.column.ticks.get(self.row(id)?)
+    }
+    pub fn len(&self) -> usize {
+        self.entity_ids.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entity_ids.is_empty()
+    }
+
+    pub fn get<T: Component>(&self, id: EntityId) -> Option<&T> {
+        let row = self.row(id)?;
+        self.column.data.downcast_ref::<T>()?.get(row)
+    }
+
+    /* Returns the old value if the entity had one, which counts as a change rather than an add. */
+    pub fn insert<T: Component>(&mut self, id: EntityId, component: T, tick: u32) -> Result<Option<T>, String> {
+        let wrong_type = || format!("sparse set is not of type {}", ComponentTypeId::of::<T>());
+        if let Some(row) = self.row(id) {
+            self.column.ticks[row].changed = tick;
+            let mut data = self.column.data.downcast_mut::<T>().ok_or_else(wrong_type)?;
+            return Ok(Some(replace(&mut data.as_mut_slice()[row], component)));
+        }
+        self.column.data.downcast_mut::<T>().ok_or_else(wrong_type)?.push(component);
+        self.column.ticks.push(ComponentTicks::new(tick));
+        self.link(id);
+        Ok(None)
+    }
+
+    /* Moves the only value of column, a column of this set's type, over for an entity that has none yet. */
+    pub(crate) fn push_column(&mut self, id: EntityId, column: &mut Column, tick: u32) {
+        assert!(!self.contains(id), "{:?} already has a value in the sparse set", id);
+        column.move_row(0, &mut self.column);
+        *self.column.ticks.last_mut().unwrap() = ComponentTicks::new(tick);
+        self.link(id);
+    }
+
+    pub fn remove<T: Component>(&mut self, id: EntityId) -> Result<Option<T>, String> {
+        let row = match self.row(id) {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let value = self
+            .column
+            .data
+            .downcast_mut::<T>()
+            .ok_or(format!("sparse set is not of type {}", ComponentTypeId::of::<T>()))?
+            .swap_remove(row);
+        self.column.ticks.swap_remove(row);
+        self.unlink(id, row);
+        Ok(Some(value))
+    }
+
+    /* Drops the entity's value without knowing its type, for despawning. */
+    pub fn remove_entity(&mut self, id: EntityId) -> bool {
+        match self.row(id) {
+            Some(row) => {
+                self.column.swap_remove(row);
+                self.unlink(id, row);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /* The same set with its values replaced by column, a clone of this set's column. */
+    pub fn clone_with(&self, column: Column) -> SparseSet {
+        SparseSet {
+            column,
+            entity_ids: self.entity_ids.clone(),
+            sparse: self.sparse.clone(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.column.clear();
+        self.entity_ids.clear();
+        self.sparse.clear();
+    }
+
+    /* Maps the entity to the last row of the column, which was just pushed. */
+    fn link(&mut self, id: EntityId) {
+        let index = id.index() as usize;
+        if self.sparse.len() <= index {
+            self.sparse.resize(index + 1, None);
+        }
+        self.sparse[index] = Some(self.entity_ids.len());
+        self.entity_ids.push(id);
+    }
+
+    /* Fixes the maps after the row was swap removed from the column. */
+    fn unlink(&mut self, id: EntityId, row: usize) {
+        self.entity_ids.swap_remove(row);
+        self.sparse[id.index() as usize] = None;
+        if let Some(swapped) = self.entity_ids.get(row) {
+            self.sparse[swapped.index() as usize] = Some(row);
+        }
+    }
+}
+
+/* The sparse sets a query's filters look at, borrowed for as long as the query lives. */
+#[derive(Default)]
+pub struct SparseBorrows<'w> {
+    borrows: Vec<(ComponentTypeId, RwLockReadGuard<'w, SparseSet>)>,
+}
+
+impl<'w> SparseBorrows<'w> {
+    pub(crate) fn push(&mut self, component: ComponentTypeId, set: RwLockReadGuard<'w, SparseSet>) {
+        if self.get(&component).is_none() {
+            self.borrows.push((component, set));
+        }
+    }
+    /* None when the component is not stored in a sparse set. */
+    pub fn get(&self, component: &ComponentTypeId) -> Option<&SparseSet> {
+        self.borrows.iter().find(|(other, _)| other == component).map(|(_, set)| &**set)
+    }
+}
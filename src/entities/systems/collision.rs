@@ -0,0 +1,444 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    entities::{
+        commands::Commands,
+        components::{
+            collider::{Collider, Shape},
+            position::Position,
+        },
+        events::Events,
+        systems::{state::QueryState, System},
+        EntityId, World,
+    },
+    map::tile::TILE_SIZE,
+    maths::vector::Vector,
+};
+
+/* Sent the tick two colliders start overlapping. first is always the smaller EntityId, each pair is sent once. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CollisionStarted {
+    pub first: EntityId,
+    pub second: EntityId,
+}
+
+/* Sent the tick two colliders stop overlapping, also when one of them was despawned or lost its Collider. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CollisionEnded {
+    pub first: EntityId,
+    pub second: EntityId,
+}
+
+impl CollisionStarted {
+    /* The entity id collided with, if id is part of this collision. */
+    pub fn other(&self, id: EntityId) -> Option<EntityId> {
+        other(self.first, self.second, id)
+    }
+}
+
+impl CollisionEnded {
+    pub fn other(&self, id: EntityId) -> Option<EntityId> {
+        other(self.first, self.second, id)
+    }
+}
+
+fn other(first: EntityId, second: EntityId, id: EntityId) -> Option<EntityId> {
+    if first == id {
+        Some(second)
+    } else if second == id {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+/* Pairs of entities whose colliders overlapped on the last run of detect_collisions, smaller EntityId first.
+ * Kept in the World rather than in the system so rolling back to a snapshot rolls it back too.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Touching(HashSet<(EntityId, EntityId)>);
+
+impl Touching {
+    pub fn contains(&self, a: EntityId, b: EntityId) -> bool {
+        self.0.contains(&if a < b { (a, b) } else { (b, a) })
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/* Adds both collision events and the Touching resource detect_collisions needs, all registered for snapshots. */
+pub fn add_collisions(world: &mut World) {
+    world.add_event::<CollisionStarted>();
+    world.add_event::<CollisionEnded>();
+    world.register_clone_resource::<Events<CollisionStarted>>();
+    world.register_clone_resource::<Events<CollisionEnded>>();
+    world.insert_resource(Touching::default());
+    world.register_clone_resource::<Touching>();
+}
+
+/* Uniform grid of TILE_SIZE cells, every inserted box listed in each cell it overlaps.
+ * Only boxes sharing a cell can overlap, so pairs only has to look at boxes that are close to each other.
+ */
+#[derive(Default)]
+pub struct SpatialHash {
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    /* First and last cell of every box, by insertion index. */
+    ranges: Vec<((i32, i32), (i32, i32))>,
+}
+
+impl SpatialHash {
+    /* Returns the index the box is known by. Indices count up from 0 since the last clear. */
+    pub fn insert(&mut self, min: Vector, max: Vector) -> usize {
+        let index = self.ranges.len();
+        let first = cell(min);
+        let last = cell(max);
+        for x in first.0..=last.0 {
+            for y in first.1..=last.1 {
+                self.cells.entry((x, y)).or_default().push(index);
+            }
+        }
+        self.ranges.push((first, last));
+        index
+    }
+
+    /* Calls visit once for every two boxes that share a cell, smaller index first. They do not have to overlap. */
+    pub fn pairs(&self, mut visit: impl FnMut(usize, usize)) {
+        for (cell, indices) in &self.cells {
+            for (i, &a) in indices.iter().enumerate() {
+                for &b in &indices[i + 1..] {
+                    // two boxes share every cell of the overlap of their ranges, only its first cell reports them
+                    let (first_a, first_b) = (self.ranges[a].0, self.ranges[b].0);
+                    if (first_a.0.max(first_b.0), first_a.1.max(first_b.1)) == *cell {
+                        visit(a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.ranges.clear();
+    }
+}
+
+fn cell(point: Vector) -> (i32, i32) {
+    let size = TILE_SIZE as f32;
+    ((point.x / size).floor() as i32, (point.y / size).floor() as i32)
+}
+
+/* Whether the two colliders at those positions overlap. Shapes that only touch do not. */
+pub fn overlaps(a: &Collider, a_position: Vector, b: &Collider, b_position: Vector) -> bool {
+    let (a_min, a_max) = a.bounds(a_position);
+    let (b_min, b_max) = b.bounds(b_position);
+    match (a.shape, b.shape) {
+        (Shape::Box { .. }, Shape::Box { .. }) => a_min.x < b_max.x && b_min.x < a_max.x && a_min.y < b_max.y && b_min.y < a_max.y,
+        (Shape::Circle { radius: a_radius }, Shape::Circle { radius: b_radius }) => {
+            let (dx, dy) = (a_min.x + a_radius - b_min.x - b_radius, a_min.y + a_radius - b_min.y - b_radius);
+            dx * dx + dy * dy < (a_radius + b_radius) * (a_radius + b_radius)
+        }
+        (Shape::Box { .. }, Shape::Circle { radius }) => box_circle(a_min, a_max, b_min, radius),
+        (Shape::Circle { radius }, Shape::Box { .. }) => box_circle(b_min, b_max, a_min, radius),
+    }
+}
+
+/* The circle is given by the top left corner of its bounding box. */
+fn box_circle(min: Vector, max: Vector, circle_min: Vector, radius: f32) -> bool {
+    let (x, y) = (circle_min.x + radius, circle_min.y + radius);
+    let (dx, dy) = (x - x.clamp(min.x, max.x), y - y.clamp(min.y, max.y));
+    dx * dx + dy * dy < radius * radius
+}
+
+/* Finds every two entities whose Colliders overlap and whose layers and masks let them collide, sending
+ * CollisionStarted for the pairs that did not overlap last run and CollisionEnded for the ones that stopped.
+ * The spatial hash is rebuilt from scratch every run. The World needs add_collisions first.
+ * Events are sent sorted by entity id, so runs replayed from a snapshot send them in the same order.
+ */
+pub fn detect_collisions() -> impl System {
+    let mut colliders = QueryState::<(&Position, &Collider)>::new();
+    let mut grid = SpatialHash::default();
+    move |world: &World, _commands: &mut Commands| detect(world, &mut colliders, &mut grid)
+}
+
+fn detect(world: &World, colliders: &mut QueryState<(&Position, &Collider)>, grid: &mut SpatialHash) {
+    let mut touching = world.resource_mut::<Touching>().expect("detect_collisions needs add_collisions to be called on the World");
+    let touching = &mut touching.0;
+    let bodies: Vec<(EntityId, Vector, Collider)> = colliders.query(world).iter().map(|(id, position, collider)| (id, position.0, *collider)).collect();
+    grid.clear();
+    for (_, position, collider) in &bodies {
+        let (min, max) = collider.bounds(*position);
+        grid.insert(min, max);
+    }
+    let mut current = HashSet::with_capacity(touching.len());
+    grid.pairs(|a, b| {
+        let (a_id, a_position, a_collider) = &bodies[a];
+        let (b_id, b_position, b_collider) = &bodies[b];
+        if a_collider.interacts_with(b_collider) && overlaps(a_collider, *a_position, b_collider, *b_position) {
+            current.insert(if a_id < b_id { (*a_id, *b_id) } else { (*b_id, *a_id) });
+        }
+    });
+
+    let mut started: Vec<_> = current.difference(touching).copied().collect();
+    let mut ended: Vec<_> = touching.difference(&current).copied().collect();
+    started.sort();
+    ended.sort();
+    if !started.is_empty() {
+        let mut events = world.event_writer::<CollisionStarted>();
+        for (first, second) in started {
+            events.send(CollisionStarted { first, second });
+        }
+    }
+    if !ended.is_empty() {
+        let mut events = world.event_writer::<CollisionEnded>();
+        for (first, second) in ended {
+            events.send(CollisionEnded { first, second });
+        }
+    }
+    *touching = current;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{
+        entities::{
+            components::{collider::Collider, position::Position},
+            events::EventCursor,
+            systems::schedule::{Schedule, Stage},
+            World,
+        },
+        maths::vector::Vector,
+    };
+
+    use super::*;
+
+    fn at(x: f32, y: f32) -> Vector {
+        Vector::new(x, y, 0.0)
+    }
+
+    #[test]
+    fn boxes_overlap() {
+        let a = Collider::new(10.0, 10.0);
+        let b = Collider::new(4.0, 20.0);
+        assert!(overlaps(&a, at(0.0, 0.0), &a, at(9.0, 9.0)));
+        assert!(overlaps(&a, at(0.0, 0.0), &b, at(3.0, -5.0)));
+        // edges that only touch
+        assert!(!overlaps(&a, at(0.0, 0.0), &a, at(10.0, 0.0)));
+        assert!(!overlaps(&a, at(0.0, 0.0), &a, at(0.0, -10.0)));
+        assert!(!overlaps(&a, at(0.0, 0.0), &a, at(10.0, 10.0)));
+        assert!(overlaps(&a.with_offset(1.0, 0.0), at(0.0, 0.0), &a, at(10.0, 0.0)));
+    }
+
+    #[test]
+    fn circles_overlap() {
+        let small = Collider::circle(5.0);
+        let big = Collider::circle(10.0);
+        assert!(overlaps(&small, at(0.0, 0.0), &small, at(9.0, 0.0)));
+        assert!(overlaps(&small, at(0.0, 0.0), &big, at(-5.0, -5.0)));
+        // centres exactly a sum of radii apart only touch
+        assert!(!overlaps(&small, at(0.0, 0.0), &small, at(10.0, 0.0)));
+        assert!(!overlaps(&small, at(0.0, 0.0), &big, at(-3.0, 4.0 + 10.0)));
+        // bounding boxes overlap, the circles do not
+        assert!(!overlaps(&small, at(0.0, 0.0), &small, at(8.0, 8.0)));
+    }
+
+    #[test]
+    fn boxes_and_circles_overlap() {
+        let square = Collider::new(10.0, 10.0);
+        let circle = Collider::circle(5.0);
+        assert!(overlaps(&square, at(0.0, 0.0), &circle, at(9.0, 2.0)));
+        assert!(overlaps(&circle, at(9.0, 2.0), &square, at(0.0, 0.0)));
+        // circle fully inside the box
+        assert!(overlaps(&square, at(0.0, 0.0), &Collider::circle(2.0), at(3.0, 3.0)));
+        // centre at (15, 5), touching the right edge
+        assert!(!overlaps(&square, at(0.0, 0.0), &circle, at(10.0, 0.0)));
+        assert!(!overlaps(&circle, at(10.0, 0.0), &square, at(0.0, 0.0)));
+        // centre at (15, 15), 7.07 away from the corner
+        assert!(!overlaps(&square, at(0.0, 0.0), &circle, at(10.0, 10.0)));
+        // centre at (13, 13), 4.24 away from the corner
+        assert!(overlaps(&square, at(0.0, 0.0), &circle, at(8.0, 8.0)));
+        assert!(overlaps(&circle.with_offset(-5.0, -5.0), at(12.0, 5.0), &square, at(0.0, 0.0)));
+    }
+
+    #[test]
+    fn pairs_are_reported_once() {
+        let mut grid = SpatialHash::default();
+        // both span several cells and share four of them
+        let wide = grid.insert(at(0.0, 0.0), at(40.0, 40.0));
+        let tall = grid.insert(at(20.0, -30.0), at(50.0, 60.0));
+        grid.insert(at(100.0, 100.0), at(110.0, 110.0));
+        let mut pairs = Vec::new();
+        grid.pairs(|a, b| pairs.push((a, b)));
+        assert_eq!(pairs, vec![(wide, tall)]);
+
+        let mut seed = 7u32;
+        let mut random = |range: f32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1u32 << 24) as f32 * range
+        };
+        grid.clear();
+        let mut boxes = Vec::new();
+        for _ in 0..300 {
+            let min = at(random(400.0) - 100.0, random(400.0) - 100.0);
+            let max = at(min.x + random(60.0), min.y + random(60.0));
+            boxes.push((cell(min), cell(max)));
+            grid.insert(min, max);
+        }
+        let mut pairs = Vec::new();
+        grid.pairs(|a, b| pairs.push((a, b)));
+        let mut expected = Vec::new();
+        for (a, (a_first, a_last)) in boxes.iter().enumerate() {
+            for (b, (b_first, b_last)) in boxes.iter().enumerate().skip(a + 1) {
+                let apart = a_last.0 < b_first.0 || b_last.0 < a_first.0 || a_last.1 < b_first.1 || b_last.1 < a_first.1;
+                if !apart {
+                    expected.push((a, b));
+                }
+            }
+        }
+        pairs.sort();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn layers_and_masks_filter_pairs() {
+        let player = Collider::new(10.0, 10.0).with_layers(0b001, 0b110);
+        let enemy = Collider::new(10.0, 10.0).with_layers(0b010, 0b001);
+        let ghost = Collider::new(10.0, 10.0).with_layers(0b100, 0b000);
+        assert!(player.interacts_with(&enemy));
+        assert!(enemy.interacts_with(&player));
+        // the player's mask has the ghost's layer, but not the other way around
+        assert!(!player.interacts_with(&ghost));
+        assert!(!enemy.interacts_with(&enemy));
+
+        let mut world = collision_world();
+        let mut schedule = collision_schedule();
+        let mut cursors = Cursors::default();
+        let player = world.spawn((Position(at(0.0, 0.0)), player));
+        let enemy = world.spawn((Position(at(5.0, 0.0)), enemy));
+        world.spawn((Position(at(0.0, 5.0)), ghost));
+        let other_enemy = world.spawn((Position(at(5.0, 5.0)), Collider::new(10.0, 10.0).with_layers(0b010, 0b001)));
+        let (started, _) = cursors.run(&mut world, &mut schedule);
+        let pairs: Vec<_> = started.iter().map(|event| (event.first, event.second)).collect();
+        assert_eq!(pairs, vec![(player, enemy), (player, other_enemy)]);
+    }
+
+    #[test]
+    fn collisions_start_and_end() {
+        let mut world = collision_world();
+        let mut schedule = collision_schedule();
+        let mut cursors = Cursors::default();
+        let a = world.spawn((Position(at(0.0, 0.0)), Collider::new(10.0, 10.0)));
+        let b = world.spawn((Position(at(5.0, 0.0)), Collider::circle(5.0)));
+        let c = world.spawn((Position(at(0.0, 5.0)), Collider::new(10.0, 10.0)));
+        let started = |first, second| CollisionStarted { first, second };
+        let ended = |first, second| CollisionEnded { first, second };
+
+        assert_eq!(cursors.run(&mut world, &mut schedule), (vec![started(a, b), started(a, c), started(b, c)], vec![]));
+        assert_eq!(cursors.run(&mut world, &mut schedule), (vec![], vec![]));
+        assert!(world.resource::<Touching>().unwrap().contains(c, a));
+
+        world.get_mut::<Position>(b).unwrap().unwrap().0 = at(50.0, 0.0);
+        assert_eq!(cursors.run(&mut world, &mut schedule), (vec![], vec![ended(a, b), ended(b, c)]));
+        world.get_mut::<Position>(b).unwrap().unwrap().0 = at(8.0, 8.0);
+        assert_eq!(cursors.run(&mut world, &mut schedule), (vec![started(a, b), started(b, c)], vec![]));
+
+        world.despawn(a).unwrap();
+        assert_eq!(cursors.run(&mut world, &mut schedule), (vec![], vec![ended(a, b), ended(a, c)]));
+        world.remove_component::<Collider>(c).unwrap();
+        assert_eq!(cursors.run(&mut world, &mut schedule), (vec![], vec![ended(b, c)]));
+        assert!(world.resource::<Touching>().unwrap().is_empty());
+        assert_eq!(started(b, c).other(c), Some(b));
+        assert_eq!(ended(b, c).other(a), None);
+    }
+
+    #[test]
+    fn thousands_of_bodies_match_brute_force() {
+        let mut world = collision_world();
+        let mut schedule = collision_schedule();
+        let mut cursors = Cursors::default();
+        let mut seed = 12345u32;
+        let mut random = move |range: f32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1u32 << 24) as f32 * range
+        };
+        let mut ids = Vec::new();
+        for i in 0..3000 {
+            let shape = if i % 2 == 0 { Collider::new(4.0 + random(20.0), 4.0 + random(20.0)) } else { Collider::circle(2.0 + random(10.0)) };
+            let collider = shape.with_offset(random(4.0) - 2.0, 0.0).with_layers(1 << (i % 3), if i % 5 == 0 { 0b001 } else { 0b111 });
+            ids.push(world.spawn((Position(at(random(700.0) - 50.0, random(400.0) - 50.0)), collider)));
+        }
+        let mut touching = HashSet::new();
+        let mut total_started = 0;
+        for tick in 0..6 {
+            if tick == 3 {
+                for id in ids.iter().step_by(7) {
+                    world.despawn(*id).unwrap();
+                }
+            }
+            for (_, mut position) in world.query::<(&mut Position,)>().iter_mut() {
+                position.0 = at(position.0.x + random(16.0) - 8.0, position.0.y + random(16.0) - 8.0);
+            }
+            let (started, ended) = cursors.run(&mut world, &mut schedule);
+            for event in &ended {
+                assert!(touching.remove(&(event.first, event.second)), "ended a pair that never started");
+            }
+            for event in &started {
+                assert!(event.first < event.second);
+                assert!(touching.insert((event.first, event.second)), "started a pair twice");
+            }
+            total_started += started.len();
+
+            let bodies: Vec<_> = world.query::<(&Position, &Collider)>().iter().map(|(id, position, collider)| (id, position.0, *collider)).collect();
+            let mut expected = HashSet::new();
+            for (i, (a, a_position, a_collider)) in bodies.iter().enumerate() {
+                for (b, b_position, b_collider) in &bodies[i + 1..] {
+                    if a_collider.interacts_with(b_collider) && overlaps(a_collider, *a_position, b_collider, *b_position) {
+                        expected.insert((*a.min(b), *a.max(b)));
+                    }
+                }
+            }
+            assert_eq!(touching, expected, "tick {}", tick);
+            assert_eq!(world.resource::<Touching>().unwrap().0, expected);
+        }
+        assert!(total_started > 1000, "only {} collisions started", total_started);
+    }
+
+    fn collision_world() -> World {
+        let mut world = World::init();
+        add_collisions(&mut world);
+        world
+    }
+
+    fn collision_schedule() -> Schedule {
+        let mut schedule = Schedule::new();
+        schedule.add_system(Stage::Physics, "detect_collisions", detect_collisions());
+        schedule
+    }
+
+    #[derive(Default)]
+    struct Cursors {
+        started: EventCursor<CollisionStarted>,
+        ended: EventCursor<CollisionEnded>,
+    }
+
+    impl Cursors {
+        /* Runs the schedule and returns the events it sent. */
+        fn run(&mut self, world: &mut World, schedule: &mut Schedule) -> (Vec<CollisionStarted>, Vec<CollisionEnded>) {
+            world.run_schedule(schedule);
+            let started = world.event_reader(&mut self.started).read().copied().collect();
+            let ended = world.event_reader(&mut self.ended).read().copied().collect();
+            (started, ended)
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::marker::PhantomData;
+
+use crate::entities::{components::{Component, ComponentTypeId}, sparse_set::{SparseBorrows, SparseSet}, Archetype, ComponentTicks, EntityId};
+
+/* Restricts which entities a query visits without fetching any data.
+ * Archetype level restrictions go through access, row level ones through filter.
+ * Components stored in sparse sets are never in an archetype, the query leaves them out of the archetype
+ * matching and hands their borrowed sets to column instead, so filter checks them row by row.
+ */
+pub trait QueryFilter {
+    type Column: Copy + 'static;
+
+    /* Pushes the components a matching archetype must have and the ones it must not have. */
+    fn access(required: &mut Vec<ComponentTypeId>, excluded: &mut Vec<ComponentTypeId>);
+    fn column(archetype: &Archetype, sparse: &SparseBorrows) -> Self::Column;
+    /** # Safety
+     * column must come from this archetype's borrow, still held, and row must be one of its rows.
+     */
+    unsafe fn filter(column: Self::Column, row: usize, id: EntityId, last_run: u32) -> bool;
+}
+
+pub struct With<T>(PhantomData<T>);
+pub struct Without<T>(PhantomData<T>);
+/* Entities that got T since the query's last run, including by spawning. */
+pub struct Added<T>(PhantomData<T>);
+/* Entities whose T was added or written since the query's last run. */
+pub struct Changed<T>(PhantomData<T>);
+
+fn sparse_ptr<T: Component>(sparse: &SparseBorrows) -> Option<*const SparseSet> {
+    sparse.get(&ComponentTypeId::of::<T>()).map(|set| set as *const SparseSet)
+}
+
+impl<T: Component> QueryFilter for With<T> {
+    type Column = Option<*const SparseSet>;
+
+    fn access(required: &mut Vec<ComponentTypeId>, _excluded: &mut Vec<ComponentTypeId>) {
+        required.push(ComponentTypeId::of::<T>());
+    }
+    fn column(_archetype: &Archetype, sparse: &SparseBorrows) -> Self::Column {
+        sparse_ptr::<T>(sparse)
+    }
+    unsafe fn filter(column: Self::Column, _row: usize, id: EntityId, _last_run: u32) -> bool {
+        column.is_none_or(|set| (*set).contains(id))
+    }
+}
+
+impl<T: Component> QueryFilter for Without<T> {
+    type Column = Option<*const SparseSet>;
+
+    fn access(_required: &mut Vec<ComponentTypeId>, excluded: &mut Vec<ComponentTypeId>) {
+        excluded.push(ComponentTypeId::of::<T>());
+    }
+    fn column(_archetype: &Archetype, sparse: &SparseBorrows) -> Self::Column {
+        sparse_ptr::<T>(sparse)
+    }
+    unsafe fn filter(column: Self::Column, _row: usize, id: EntityId, _last_run: u32) -> bool {
+        column.is_none_or(|set| !(*set).contains(id))
+    }
+}
+
+/* Where Added and Changed find the ticks of their component. */
+#[derive(Copy, Clone)]
+pub enum TicksColumn {
+    Dense(*const ComponentTicks),
+    Sparse(*const SparseSet),
+}
+
+impl TicksColumn {
+    fn of<T: Component>(archetype: &Archetype, sparse: &SparseBorrows) -> Self {
+        if let Some(set) = sparse_ptr::<T>(sparse) {
+            return TicksColumn::Sparse(set);
+        }
+        let ticks = archetype
+            .components
+            .get(&ComponentTypeId::of::<T>())
+            .expect("query matched an archetype without one of its filtered components")
+            .ticks
+            .as_ptr();
+        TicksColumn::Dense(ticks)
+    }
+    unsafe fn get<'a>(self, row: usize, id: EntityId) -> Option<&'a ComponentTicks> {
+        match self {
+            TicksColumn::Dense(ticks) => Some(&*ticks.add(row)),
+            TicksColumn::Sparse(set) => (*set).ticks(id),
+        }
+    }
+}
+
+impl<T: Component> QueryFilter for Added<T> {
+    type Column = TicksColumn;
+
+    fn access(required: &mut Vec<ComponentTypeId>, _excluded: &mut Vec<ComponentTypeId>) {
+        required.push(ComponentTypeId::of::<T>());
+    }
+    fn column(archetype: &Archetype, sparse: &SparseBorrows) -> Self::Column {
+        TicksColumn::of::<T>(archetype, sparse)
+    }
+    unsafe fn filter(column: Self::Column, row: usize, id: EntityId, last_run: u32) -> bool {
+        column.get(row, id).is_some_and(|ticks| ticks.is_added(last_run))
+    }
+}
+
+impl<T: Component> QueryFilter for Changed<T> {
+    type Column = TicksColumn;
+
+    fn access(required: &mut Vec<ComponentTypeId>, _excluded: &mut Vec<ComponentTypeId>) {
+        required.push(ComponentTypeId::of::<T>());
+    }
+    fn column(archetype: &Archetype, sparse: &SparseBorrows) -> Self::Column {
+        TicksColumn::of::<T>(archetype, sparse)
+    }
+    unsafe fn filter(column: Self::Column, row: usize, id: EntityId, last_run: u32) -> bool {
+        column.get(row, id).is_some_and(|ticks| ticks.is_changed(last_run))
+    }
+}
+
+macro_rules! filter_impls {
+    ($( $name:ident )*) => {
+        #[allow(non_snake_case, unused_variables, clippy::unused_unit)]
+        impl<$($name: QueryFilter),*> QueryFilter for ($($name,)*)
+        {
+            type Column = ($($name::Column,)*);
+
+            fn access(required: &mut Vec<ComponentTypeId>, excluded: &mut Vec<ComponentTypeId>) {
+                $($name::access(required, excluded);)*
+            }
+            fn column(archetype: &Archetype, sparse: &SparseBorrows) -> Self::Column {
+                ($($name::column(archetype, sparse),)*)
+            }
+            unsafe fn filter(column: Self::Column, row: usize, id: EntityId, last_run: u32) -> bool {
+                let ($($name,)*) = column;
+                true $(&& $name::filter($name, row, id, last_run))*
+            }
+        }
+    };
+}
+
+filter_impls! {}
+filter_impls! { A }
+filter_impls! { A B }
+filter_impls! { A B C }
+filter_impls! { A B C D }
+filter_impls! { A B C D E }
+filter_impls! { A B C D E F }
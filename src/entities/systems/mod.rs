@@ -1,67 +1,392 @@
-use super::{components::{Component, ComponentTypeId}, World};
+use std::{any::Any, marker::PhantomData, ops::{Deref, DerefMut}, sync::{RwLockReadGuard, RwLockWriteGuard}};
 
+use super::{commands::Commands, components::{Component, ComponentTypeId}, sparse_set::SparseBorrows, Archetype, ArchetypeId, ComponentTicks, EntityId, World};
 
-pub struct Query<'a> {
-    world: &'a World,
-    archetypes: Vec<u64>,
-    components: Vec<ComponentTypeId>,
+use self::{filter::QueryFilter, state::QueryState};
+
+pub mod collision;
+pub mod filter;
+pub mod movement;
+pub mod schedule;
+pub mod state;
+pub mod tile_collision;
+pub mod transform;
+
+/* Anything the schedule can run once per tick. Plain functions taking &World and &mut Commands are systems too.
+ * Structural changes go through the commands, which are applied once the system's stage has finished.
+ * Systems may run on another thread, alongside others that do not touch the same data.
+ */
+pub trait System: Send {
+    fn run(&mut self, world: &World, commands: &mut Commands);
+    /* Copy of whatever the system keeps between runs that changes what it does, taken along with World snapshots.
+     * Caches that rebuild themselves, like a QueryState, do not count. Systems without such state return None.
+     */
+    fn save_state(&self) -> Option<SavedState> {
+        None
+    }
+    /* Puts back state returned by save_state. */
+    fn restore_state(&mut self, _state: &SavedState) {}
 }
 
-impl Query<'_> {
+pub type SavedState = Box<dyn Any + Send + Sync>;
+
+impl<F: FnMut(&World, &mut Commands) + Send> System for F {
+    fn run(&mut self, world: &World, commands: &mut Commands) {
+        self(world, commands)
+    }
 }
 
-pub trait IntoQuery {
-    fn query(world: &World) -> Query;
+/* System keeping state between runs that snapshots can roll back, like the EventCursor of an event reader.
+ * run gets the state along with the world, anything else it captures is left out of snapshots.
+ */
+pub fn stateful<S, F>(state: S, run: F) -> impl System
+where
+    S: Clone + Send + Sync + 'static,
+    F: FnMut(&mut S, &World, &mut Commands) + Send,
+{
+    Stateful { state, run }
 }
 
+struct Stateful<S, F> {
+    state: S,
+    run: F,
+}
 
+impl<S, F> System for Stateful<S, F>
+where
+    S: Clone + Send + Sync + 'static,
+    F: FnMut(&mut S, &World, &mut Commands) + Send,
+{
+    fn run(&mut self, world: &World, commands: &mut Commands) {
+        (self.run)(&mut self.state, world, commands)
+    }
+    fn save_state(&self) -> Option<SavedState> {
+        Some(Box::new(self.state.clone()))
+    }
+    fn restore_state(&mut self, state: &SavedState) {
+        self.state = state.downcast_ref::<S>().expect("system state restored into another system").clone();
+    }
+}
 
-macro_rules! tuple_impls {
-    ($first:ident $( $name:ident )+ ) => {
-        impl<$first: Component,$($name: Component),+> IntoQuery for ($first,$($name,)+)
-        {
-            fn query(world: &World) -> Query {
-                if let Some(archetype_set) = world.archetype_sets.get(&ComponentTypeId::of::<$first>()) {
-                    Query {
-                        world,
-                        archetypes: archetype_set.iter().copied().filter(|k| $(world.archetype_sets.get(&ComponentTypeId::of::<$name>()).and_then(|set| Some(set.contains(k))).unwrap_or(false))&&+).collect(),
-                        components: vec![$(ComponentTypeId::of::<$name>()),+]
-                    }
-                } else {
-                    Query {
-                        world,
-                        archetypes: Vec::new(),
-                        components: vec![$(ComponentTypeId::of::<$name>()),+]
-                    }
-                }
-            }
+enum ArchetypeBorrows<'w> {
+    Shared(Vec<RwLockReadGuard<'w, Archetype>>),
+    Exclusive(Vec<RwLockWriteGuard<'w, Archetype>>),
+}
+
+/* Holds a borrow of every archetype it matched until it is dropped.
+ * Queries with any &mut element borrow their archetypes mutably, so two of them overlapping will panic.
+ * Added<T> and Changed<T> filters compare against last_run, writes through iter_mut are stamped with this_run.
+ * Sparse components can only appear in the filter, their sets are borrowed shared.
+ */
+pub struct Query<'w, Q: IntoQuery, F: QueryFilter = ()> {
+    borrows: ArchetypeBorrows<'w>,
+    sparse: SparseBorrows<'w>,
+    last_run: u32,
+    this_run: u32,
+    marker: PhantomData<fn() -> (Q, F)>,
+}
+
+impl<'w, Q: IntoQuery, F: QueryFilter> Query<'w, Q, F> {
+    /* Matches the archetypes from scratch, keep a QueryState around instead to do that incrementally. */
+    pub fn new(world: &'w World, last_run: u32) -> Self {
+        QueryState::new().query_since(world, last_run)
+    }
+
+    fn borrow(world: &'w World, archetypes: &[ArchetypeId], sparse_components: &[ComponentTypeId], last_run: u32) -> Self {
+        let mut sparse = SparseBorrows::default();
+        for component in sparse_components {
+            sparse.push(*component, world.sparse_sets[component].borrow());
         }
-    };
+        let borrows = if Q::READ_ONLY {
+            ArchetypeBorrows::Shared(archetypes.iter().map(|id| world.archetypes[*id as usize].borrow()).collect())
+        } else {
+            ArchetypeBorrows::Exclusive(archetypes.iter().map(|id| world.archetypes[*id as usize].borrow_mut()).collect())
+        };
+        Query {
+            borrows,
+            sparse,
+            last_run,
+            this_run: world.change_tick(),
+            marker: PhantomData,
+        }
+    }
+
+    fn borrowed(&self) -> impl Iterator<Item = &Archetype> {
+        let (shared, exclusive): (&[RwLockReadGuard<Archetype>], &[RwLockWriteGuard<Archetype>]) = match &self.borrows {
+            ArchetypeBorrows::Shared(borrows) => (borrows, &[]),
+            ArchetypeBorrows::Exclusive(borrows) => (&[], borrows),
+        };
+        shared.iter().map(|archetype| &**archetype).chain(exclusive.iter().map(|archetype| &**archetype))
+    }
+
+    fn rows(&self) -> impl Iterator<Item = (Q::Columns, EntityId, usize)> + '_ {
+        let (sparse, last_run) = (&self.sparse, self.last_run);
+        self.borrowed().flat_map(move |archetype| filtered_rows::<F, _>(archetype, Q::columns(archetype), sparse, last_run))
+    }
+
+    /* Like rows, but the columns of exclusively borrowed archetypes come from their write guards so they can be written through. */
+    fn rows_mut(&mut self) -> impl Iterator<Item = (Q::Columns, EntityId, usize)> + '_ {
+        let (sparse, last_run) = (&self.sparse, self.last_run);
+        let archetypes: Vec<(Q::Columns, &Archetype)> = match &mut self.borrows {
+            ArchetypeBorrows::Shared(borrows) => borrows.iter().map(|archetype| (Q::columns(archetype), &**archetype)).collect(),
+            ArchetypeBorrows::Exclusive(borrows) => borrows
+                .iter_mut()
+                .map(|archetype| {
+                    let archetype = &mut **archetype;
+                    (Q::columns_mut(archetype), &*archetype)
+                })
+                .collect(),
+        };
+        archetypes
+            .into_iter()
+            .flat_map(move |(columns, archetype)| filtered_rows::<F, _>(archetype, columns, sparse, last_run))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Q::Item<'_>> {
+        self.rows().map(|(columns, id, row)| unsafe { Q::fetch(columns, id, row) })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = Q::ItemMut<'_>> {
+        let this_run = self.this_run;
+        // &mut self guarantees no other item from this query is alive, and every row is only visited once
+        self.rows_mut().map(move |(columns, id, row)| unsafe { Q::fetch_mut(columns, id, row, this_run) })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows().next().is_none()
+    }
+}
+
+fn filtered_rows<'a, F: QueryFilter, C: Copy + 'a>(
+    archetype: &'a Archetype,
+    columns: C,
+    sparse: &SparseBorrows,
+    last_run: u32,
+) -> impl Iterator<Item = (C, EntityId, usize)> + 'a {
+    let filter = F::column(archetype, sparse);
+    archetype
+        .entity_ids
+        .iter()
+        .enumerate()
+        .filter(move |(row, id)| unsafe { F::filter(filter, *row, **id, last_run) })
+        .map(move |(row, id)| (columns, *id, row))
+}
+
+/* One element of a query tuple, &T, &mut T or an Option of either.
+ * Columns are raw pointers into the archetype storage so several can be handed out mutably at once.
+ */
+pub trait QueryParam {
+    type Item<'a>;
+    type ItemMut<'a>;
+    type Column: Copy + 'static;
+    const READ_ONLY: bool;
+    const REQUIRED: bool = true;
+
+    fn component() -> ComponentTypeId;
+    /* Columns from a shared borrow are only ever read through. */
+    fn column(archetype: &Archetype) -> Self::Column;
+    fn column_mut(archetype: &mut Archetype) -> Self::Column {
+        Self::column(archetype)
+    }
+    /** # Safety
+     * column must come from an archetype borrow that is still held and row must be one of its rows.
+     * fetch_mut also needs the borrow to be exclusive and row to not be fetched twice.
+     */
+    unsafe fn fetch<'a>(column: Self::Column, row: usize) -> Self::Item<'a>;
+    /** # Safety
+     * See fetch.
+     */
+    unsafe fn fetch_mut<'a>(column: Self::Column, row: usize, this_run: u32) -> Self::ItemMut<'a>;
+}
+
+fn column_ptr<T: Component>(archetype: &Archetype) -> (*const T, *const ComponentTicks) {
+    let storage = archetype
+        .components
+        .get(&ComponentTypeId::of::<T>())
+        .expect("query matched an archetype without one of its components");
+    (storage.data.as_bytes() as *const T, storage.ticks.as_ptr())
+}
+
+/* Only pointers taken from an exclusive borrow may be written through. */
+fn column_mut_ptr<T: Component>(archetype: &mut Archetype) -> (*mut T, *mut ComponentTicks) {
+    let storage = archetype
+        .components
+        .get_mut(&ComponentTypeId::of::<T>())
+        .expect("query matched an archetype without one of its components");
+    let data = storage.data.downcast_mut::<T>().expect("column holds another type").as_mut_slice().as_mut_ptr();
+    (data, storage.ticks.as_mut_ptr())
 }
 
+/* Mutable access to a component handed out by iter_mut. Only dereferencing it mutably marks the component as changed. */
+pub struct Mut<'a, T> {
+    value: &'a mut T,
+    ticks: &'a mut ComponentTicks,
+    this_run: u32,
+}
+
+impl<'a, T> Mut<'a, T> {
+    pub fn ticks(&self) -> ComponentTicks {
+        *self.ticks
+    }
+    /* Writes through the returned reference are not detected by Changed<T>. */
+    pub fn bypass_change_detection(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<'a, T> Deref for Mut<'a, T> {
+    type Target = T;
 
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T> DerefMut for Mut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.ticks.changed = self.this_run;
+        self.value
+    }
+}
+
+impl<T: Component> QueryParam for &T {
+    type Item<'a> = &'a T;
+    type ItemMut<'a> = &'a T;
+    type Column = *const T;
+    const READ_ONLY: bool = true;
+
+    fn component() -> ComponentTypeId {
+        ComponentTypeId::of::<T>()
+    }
+    fn column(archetype: &Archetype) -> Self::Column {
+        column_ptr::<T>(archetype).0
+    }
+    unsafe fn fetch<'a>(column: Self::Column, row: usize) -> Self::Item<'a> {
+        &*column.add(row)
+    }
+    unsafe fn fetch_mut<'a>(column: Self::Column, row: usize, _this_run: u32) -> Self::ItemMut<'a> {
+        &*column.add(row)
+    }
+}
 
+impl<T: Component> QueryParam for &mut T {
+    type Item<'a> = &'a T;
+    type ItemMut<'a> = Mut<'a, T>;
+    type Column = (*mut T, *mut ComponentTicks);
+    const READ_ONLY: bool = false;
 
-impl<T: Component> IntoQuery for (T,) {
-    fn query(world: &World) -> Query {
-        if let Some(archetype_set) = world.archetype_sets.get(&ComponentTypeId::of::<T>()) {
-            Query {
-                world,
-                archetypes: archetype_set.iter().copied().collect(),
-                components: vec![ComponentTypeId::of::<T>()]
-            } 
+    fn component() -> ComponentTypeId {
+        ComponentTypeId::of::<T>()
+    }
+    /* Only reached through iter, which never writes. */
+    fn column(archetype: &Archetype) -> Self::Column {
+        let (data, ticks) = column_ptr::<T>(archetype);
+        (data as *mut T, ticks as *mut ComponentTicks)
+    }
+    fn column_mut(archetype: &mut Archetype) -> Self::Column {
+        column_mut_ptr::<T>(archetype)
+    }
+    unsafe fn fetch<'a>(column: Self::Column, row: usize) -> Self::Item<'a> {
+        &*column.0.add(row)
+    }
+    unsafe fn fetch_mut<'a>(column: Self::Column, row: usize, this_run: u32) -> Self::ItemMut<'a> {
+        Mut {
+            value: &mut *column.0.add(row),
+            ticks: &mut *column.1.add(row),
+            this_run,
         }
-        else {
-            Query {
-                world,
-                archetypes: Vec::new(),
-                components: vec![ComponentTypeId::of::<T>()]
+    }
+}
+
+impl<P: QueryParam> QueryParam for Option<P> {
+    type Item<'a> = Option<P::Item<'a>>;
+    type ItemMut<'a> = Option<P::ItemMut<'a>>;
+    type Column = Option<P::Column>;
+    const READ_ONLY: bool = P::READ_ONLY;
+    const REQUIRED: bool = false;
+
+    fn component() -> ComponentTypeId {
+        P::component()
+    }
+    fn column(archetype: &Archetype) -> Self::Column {
+        archetype.components.contains_key(&P::component()).then(|| P::column(archetype))
+    }
+    fn column_mut(archetype: &mut Archetype) -> Self::Column {
+        archetype.components.contains_key(&P::component()).then(|| P::column_mut(archetype))
+    }
+    unsafe fn fetch<'a>(column: Self::Column, row: usize) -> Self::Item<'a> {
+        column.map(|column| P::fetch(column, row))
+    }
+    unsafe fn fetch_mut<'a>(column: Self::Column, row: usize, this_run: u32) -> Self::ItemMut<'a> {
+        column.map(|column| P::fetch_mut(column, row, this_run))
+    }
+}
+
+pub trait IntoQuery: Sized {
+    type Item<'a>;
+    type ItemMut<'a>;
+    type Columns: Copy + 'static;
+    const READ_ONLY: bool;
+
+    fn query(world: &World) -> Query<'_, Self> {
+        Query::new(world, world.last_run())
+    }
+    /* Pushes every component the query reads or writes, and the ones an archetype must have to match. */
+    fn access(components: &mut Vec<ComponentTypeId>, required: &mut Vec<ComponentTypeId>);
+    fn columns(archetype: &Archetype) -> Self::Columns;
+    fn columns_mut(archetype: &mut Archetype) -> Self::Columns;
+    /** # Safety
+     * Same as QueryParam::fetch, for every element of the query.
+     */
+    unsafe fn fetch<'a>(columns: Self::Columns, id: EntityId, row: usize) -> Self::Item<'a>;
+    /** # Safety
+     * Same as QueryParam::fetch_mut, for every element of the query.
+     */
+    unsafe fn fetch_mut<'a>(columns: Self::Columns, id: EntityId, row: usize, this_run: u32) -> Self::ItemMut<'a>;
+}
+
+
+
+macro_rules! tuple_impls {
+    ($( $name:ident )+) => {
+        #[allow(non_snake_case)]
+        impl<$($name: QueryParam),+> IntoQuery for ($($name,)+)
+        {
+            type Item<'a> = (EntityId, $($name::Item<'a>,)+);
+            type ItemMut<'a> = (EntityId, $($name::ItemMut<'a>,)+);
+            type Columns = ($($name::Column,)+);
+            const READ_ONLY: bool = $($name::READ_ONLY)&&+;
+
+            fn access(components: &mut Vec<ComponentTypeId>, required: &mut Vec<ComponentTypeId>) {
+                $(
+                    components.push($name::component());
+                    if $name::REQUIRED {
+                        required.push($name::component());
+                    }
+                )+
+            }
+            fn columns(archetype: &Archetype) -> Self::Columns {
+                ($($name::column(archetype),)+)
+            }
+            fn columns_mut(archetype: &mut Archetype) -> Self::Columns {
+                ($($name::column_mut(archetype),)+)
+            }
+            unsafe fn fetch<'a>(columns: Self::Columns, id: EntityId, row: usize) -> Self::Item<'a> {
+                let ($($name,)+) = columns;
+                (id, $($name::fetch($name, row),)+)
+            }
+            unsafe fn fetch_mut<'a>(columns: Self::Columns, id: EntityId, row: usize, this_run: u32) -> Self::ItemMut<'a> {
+                let ($($name,)+) = columns;
+                (id, $($name::fetch_mut($name, row, this_run),)+)
             }
         }
-    }
+    };
 }
 
 
+
+
+tuple_impls! { A }
 tuple_impls! { A B }
 tuple_impls! { A B C }
 tuple_impls! { A B C D }
@@ -75,3 +400,123 @@ tuple_impls! { A B C D E F G H I J K }
 tuple_impls! { A B C D E F G H I J K L }
 
 
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::entities::{commands::Commands, components::Component, EntityId, World};
+
+    use super::{
+        filter::{Added, Changed},
+        schedule::{Schedule, Stage},
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct A(u32);
+    #[derive(Debug, PartialEq)]
+    struct B(u32);
+    struct Sparse;
+
+    fn changed<T: Component>(world: &World) -> Vec<EntityId> {
+        let mut ids: Vec<_> = world.query_filtered::<(&T,), Changed<T>>().iter().map(|(id, _)| id).collect();
+        ids.sort();
+        ids
+    }
+
+    fn added<T: Component>(world: &World) -> Vec<EntityId> {
+        let mut ids: Vec<_> = world.query_filtered::<(&T,), Added<T>>().iter().map(|(id, _)| id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn changed_and_added_see_every_kind_of_write() {
+        let mut world = World::init();
+        world.register_sparse::<Sparse>().unwrap();
+        let ids: Vec<_> = (0..5).map(|i| world.spawn((A(i), B(i)))).collect();
+        assert_eq!(added::<A>(&world), ids);
+        assert_eq!(changed::<A>(&world), ids);
+        world.advance_tick();
+        assert!(changed::<A>(&world).is_empty() && changed::<B>(&world).is_empty());
+
+        for (_, mut a, b) in world.query::<(&mut A, Option<&mut B>)>().iter_mut() {
+            if a.0 == 0 {
+                a.0 = 10;
+            }
+            if let Some(mut b) = b.filter(|b| b.0 == 4) {
+                b.0 = 40;
+            }
+        }
+        world.get_mut::<A>(ids[1]).unwrap().unwrap().0 = 11;
+        world.set_component(ids[2], A(12)).unwrap();
+        world.set_component(ids[3], Sparse).unwrap();
+        assert_eq!(changed::<A>(&world), vec![ids[0], ids[1], ids[2]]);
+        assert_eq!(changed::<B>(&world), vec![ids[4]]);
+        // overwriting is a change, not an addition
+        assert!(added::<A>(&world).is_empty());
+        // sparse components can only be filtered on, their ticks come from the set
+        let sparse = |world: &World| {
+            let changed: Vec<_> = world.query_filtered::<(&A,), Changed<Sparse>>().iter().map(|(id, _)| id).collect();
+            let added: Vec<_> = world.query_filtered::<(&A,), Added<Sparse>>().iter().map(|(id, _)| id).collect();
+            (changed, added)
+        };
+        assert_eq!(sparse(&world), (vec![ids[3]], vec![ids[3]]));
+
+        world.advance_tick();
+        world.set_component(ids[3], Sparse).unwrap();
+        assert_eq!(sparse(&world), (vec![ids[3]], vec![]));
+        assert!(changed::<A>(&world).is_empty() && changed::<B>(&world).is_empty());
+    }
+
+    #[test]
+    fn reading_through_mut_is_not_a_change() {
+        let mut world = World::init();
+        let ids: Vec<_> = (0..3).map(|i| world.spawn((A(i),))).collect();
+        world.advance_tick();
+        let mut sum = 0;
+        for (_, mut a) in world.query::<(&mut A,)>().iter_mut() {
+            sum += a.0;
+            let ticks = a.ticks();
+            a.bypass_change_detection().0 += 100;
+            assert_eq!(a.ticks(), ticks);
+        }
+        assert_eq!(sum, 3);
+        let a = world.get_mut::<A>(ids[0]).unwrap().unwrap();
+        assert_eq!(a.0, 100);
+        drop(a);
+        assert!(changed::<A>(&world).is_empty());
+    }
+
+    #[test]
+    fn systems_do_not_see_their_own_writes() {
+        let mut world = World::init();
+        let first = world.spawn((A(0),));
+        world.spawn((A(0),));
+        let seen: Arc<Mutex<Vec<usize>>> = Arc::default();
+        let mut schedule = Schedule::new();
+        let counts = seen.clone();
+        schedule.add_system(Stage::Update, "bump", move |world: &World, _: &mut Commands| {
+            counts.lock().unwrap().push(changed::<A>(world).len());
+            for (_, mut a) in world.query::<(&mut A,)>().iter_mut() {
+                a.0 += 1;
+            }
+        });
+        for _ in 0..3 {
+            world.run_schedule(&mut schedule);
+        }
+        // spawning counts as a change on the first run, after that the only writes are bump's own
+        assert_eq!(*seen.lock().unwrap(), vec![2, 0, 0]);
+
+        // writes made after bump in the same run are seen on its next one
+        schedule
+            .add_system(Stage::Update, "poke", move |world: &World, _: &mut Commands| {
+                world.get_mut::<A>(first).unwrap().unwrap().0 += 1;
+            })
+            .after("bump");
+        for _ in 0..3 {
+            world.run_schedule(&mut schedule);
+        }
+        assert_eq!(*seen.lock().unwrap(), vec![2, 0, 0, 0, 1, 1]);
+        assert_eq!(world.get::<A>(first).unwrap().unwrap().0, 9);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{
+    entities::{
+        commands::Commands,
+        components::{
+            motion::{Acceleration, Friction, MaxSpeed, Velocity},
+            position::Position,
+        },
+        systems::{state::QueryState, System},
+        World,
+    },
+    maths::vector::Vector,
+};
+
+/* Seconds that pass in one run of the schedule, a resource apply_motion reads. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Timestep(pub f32);
+
+impl Timestep {
+    pub fn from_fps(fps: u32) -> Self {
+        Timestep(1.0 / fps as f32)
+    }
+}
+
+/* Integrates every entity with a Position and a Velocity over one Timestep. Acceleration goes into the velocity
+ * first, then friction and the speed limit are applied, then the position moves by the new velocity.
+ * Entities with a Collider move like any other, collide_with_tiles scheduled after this pulls them back out of walls.
+ * Components are only written when they actually change, so resting entities do not show up in Changed.
+ */
+pub fn apply_motion() -> impl System {
+    let mut moving = QueryState::new();
+    move |world: &World, _commands: &mut Commands| integrate(world, &mut moving)
+}
+
+type Moving = (&'static mut Position, &'static mut Velocity, Option<&'static Acceleration>, Option<&'static Friction>, Option<&'static MaxSpeed>);
+
+fn integrate(world: &World, moving: &mut QueryState<Moving>) {
+    let dt = world.resource::<Timestep>().expect("apply_motion needs a Timestep resource").0;
+    let mut moving = moving.query(world);
+    for (_, mut position, mut velocity, acceleration, friction, max_speed) in moving.iter_mut() {
+        let mut new_velocity = velocity.0;
+        if let Some(acceleration) = acceleration {
+            new_velocity = new_velocity + acceleration.0 * dt;
+        }
+        if let Some(friction) = friction {
+            new_velocity = new_velocity * (1.0 - friction.0 * dt).max(0.0);
+        }
+        if let Some(max_speed) = max_speed {
+            let speed = new_velocity.mag_2d();
+            if speed > max_speed.0 {
+                new_velocity = new_velocity * (max_speed.0 / speed);
+            }
+        }
+        if new_velocity != velocity.0 {
+            velocity.0 = new_velocity;
+        }
+        if new_velocity.x != 0.0 || new_velocity.y != 0.0 {
+            position.0 = position.0 + Vector::new(new_velocity.x, new_velocity.y, 0.0) * dt;
+        }
+    }
+}
@@ -0,0 +1,581 @@
+use std::{
+    any::TypeId,
+    collections::HashSet,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use crate::entities::{
+    commands::{CommandQueue, Commands},
+    components::{Component, ComponentTypeId},
+    resources::Resource,
+    ArchetypeId, World,
+};
+
+use super::{SavedState, System};
+
+/* Stages run in declaration order, every system of a stage finishes before the next stage starts.
+ * The end of a stage is the sync point where the commands of its systems are applied.
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+    Input,
+    Update,
+    Physics,
+    RenderPrep,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Stage::Input, Stage::Update, Stage::Physics, Stage::RenderPrep];
+}
+
+/* Components and resources a system reads and writes. */
+#[derive(Clone, Debug, Default)]
+pub struct Access {
+    reads: Vec<ComponentTypeId>,
+    writes: Vec<ComponentTypeId>,
+    resource_reads: Vec<TypeId>,
+    resource_writes: Vec<TypeId>,
+}
+
+impl Access {
+    /* Two systems conflict when either writes something the other reads or writes. */
+    pub fn conflicts(&self, other: &Access) -> bool {
+        let overlap = |a: &[ComponentTypeId], b: &[ComponentTypeId]| a.iter().any(|name| b.contains(name));
+        let resource_overlap = |a: &[TypeId], b: &[TypeId]| a.iter().any(|id| b.contains(id));
+        overlap(&self.writes, &other.reads)
+            || overlap(&self.writes, &other.writes)
+            || overlap(&other.writes, &self.reads)
+            || resource_overlap(&self.resource_writes, &other.resource_reads)
+            || resource_overlap(&self.resource_writes, &other.resource_writes)
+            || resource_overlap(&other.resource_writes, &self.resource_reads)
+    }
+
+    /* Archetypes holding any component this reads or writes, a superset of what its queries match. */
+    fn archetypes(&self, world: &World) -> HashSet<ArchetypeId> {
+        self.reads
+            .iter()
+            .chain(&self.writes)
+            .filter_map(|component| world.archetype_sets.get(component))
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
+
+pub struct SystemEntry {
+    label: &'static str,
+    stage: Stage,
+    system: Box<dyn System>,
+    commands: CommandQueue,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    /* None until the system declares what it touches, until then it never runs alongside another system. */
+    access: Option<Access>,
+    last_run: u32,
+}
+
+impl SystemEntry {
+    /* Runs this system before the labelled one. Labels in other stages must agree with the stage order. */
+    pub fn before(&mut self, label: &'static str) -> &mut Self {
+        self.before.push(label);
+        self
+    }
+    pub fn after(&mut self, label: &'static str) -> &mut Self {
+        self.after.push(label);
+        self
+    }
+    /* Access is not checked up front, a system touching something it did not declare
+     * panics on the overlapping borrow if it happens to run alongside a system using it.
+     */
+    pub fn reads<T: Component>(&mut self) -> &mut Self {
+        self.access.get_or_insert_with(Access::default).reads.push(ComponentTypeId::of::<T>());
+        self
+    }
+    pub fn writes<T: Component>(&mut self) -> &mut Self {
+        self.access.get_or_insert_with(Access::default).writes.push(ComponentTypeId::of::<T>());
+        self
+    }
+    pub fn reads_resource<R: Resource>(&mut self) -> &mut Self {
+        self.access.get_or_insert_with(Access::default).resource_reads.push(TypeId::of::<R>());
+        self
+    }
+    pub fn writes_resource<R: Resource>(&mut self) -> &mut Self {
+        self.access.get_or_insert_with(Access::default).resource_writes.push(TypeId::of::<R>());
+        self
+    }
+    /* For systems that only touch their own state and the commands. */
+    pub fn no_access(&mut self) -> &mut Self {
+        self.access.get_or_insert_with(Access::default);
+        self
+    }
+    fn conflicts(&self, other: &SystemEntry) -> bool {
+        match (&self.access, &other.access) {
+            (Some(access), Some(other)) => access.conflicts(other),
+            _ => true,
+        }
+    }
+}
+
+/* Systems that can run at the same time, in the order they were sorted into. */
+type Batch = Vec<usize>;
+
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<SystemEntry>,
+    order: Option<Vec<Vec<Batch>>>,
+    /* Started the first time a batch has more than one system. */
+    workers: Option<WorkerPool>,
+    /* Threads batches run on, the calling one included. None for one per core. */
+    threads: Option<usize>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Schedule::default()
+    }
+    /* Runs batches on that many threads whatever the machine has, 1 runs every system on the calling thread. */
+    pub fn with_threads(threads: usize) -> Self {
+        assert!(threads > 0, "a schedule needs at least one thread");
+        Schedule {
+            threads: Some(threads),
+            ..Schedule::default()
+        }
+    }
+
+    pub fn add_system<S: System + 'static>(&mut self, stage: Stage, label: &'static str, system: S) -> &mut SystemEntry {
+        assert!(
+            self.systems.iter().all(|entry| entry.label != label),
+            "system {} was added to the schedule twice",
+            label
+        );
+        self.order = None;
+        self.systems.push(SystemEntry {
+            label,
+            stage,
+            system: Box::new(system),
+            commands: CommandQueue::new(),
+            before: Vec::new(),
+            after: Vec::new(),
+            access: None,
+            last_run: 0,
+        });
+        self.systems.last_mut().unwrap()
+    }
+
+    /* Tick each system last ran at, in the order they were added. */
+    pub fn system_ticks(&self) -> Vec<u32> {
+        self.systems.iter().map(|entry| entry.last_run).collect()
+    }
+    /* Puts back ticks taken by system_ticks, when the world is restored to a snapshot. */
+    pub fn set_system_ticks(&mut self, ticks: &[u32]) -> Result<(), String> {
+        if ticks.len() != self.systems.len() {
+            return Err(format!("{} system ticks given for {} systems", ticks.len(), self.systems.len()));
+        }
+        for (entry, tick) in self.systems.iter_mut().zip(ticks) {
+            entry.last_run = *tick;
+        }
+        Ok(())
+    }
+
+    /* State of each system as returned by System::save_state, in the order they were added. */
+    pub fn system_states(&self) -> Vec<Option<SavedState>> {
+        self.systems.iter().map(|entry| entry.system.save_state()).collect()
+    }
+    /* Puts back states taken by system_states, when the world is restored to a snapshot. */
+    pub fn set_system_states(&mut self, states: &[Option<SavedState>]) -> Result<(), String> {
+        if states.len() != self.systems.len() {
+            return Err(format!("{} system states given for {} systems", states.len(), self.systems.len()));
+        }
+        for (entry, state) in self.systems.iter_mut().zip(states) {
+            if let Some(state) = state {
+                entry.system.restore_state(state);
+            }
+        }
+        Ok(())
+    }
+
+    /* Event buffers are swapped before any system runs.
+     * Every batch runs at its own tick, so a system sees all writes made since it last ran, including
+     * the ones made later in the same schedule run by systems ordered after it, but never its own.
+     */
+    pub fn run(&mut self, world: &mut World) {
+        if self.order.is_none() {
+            self.order = Some(self.build_order().unwrap_or_else(|e| panic!("invalid schedule: {}", e)));
+        }
+        if self.workers.is_none() && self.order.iter().flatten().flatten().any(|batch| batch.len() > 1) {
+            let threads = self.threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get()));
+            self.workers = Some(WorkerPool::new(threads));
+        }
+        world.update_events();
+        let start_tick = world.change_tick();
+        for stage in self.order.as_ref().unwrap() {
+            for batch in stage {
+                let mut entries: Vec<&mut SystemEntry> = self
+                    .systems
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(index, _)| batch.contains(index))
+                    .map(|(_, entry)| entry)
+                    .collect();
+                // Systems added since the last run have seen fewer ticks than the rest of their batch,
+                // and world.last_run is shared by everything running at once, so those batches run one by one.
+                let last_run = entries[0].last_run;
+                if entries.iter().all(|entry| entry.last_run == last_run) {
+                    world.last_run = last_run;
+                    for entry in entries.iter_mut() {
+                        entry.last_run = world.change_tick();
+                    }
+                    for mut group in split_by_archetype(world, entries) {
+                        run_batch(world, &mut group, self.workers.as_ref());
+                    }
+                    world.change_tick += 1;
+                } else {
+                    for entry in entries {
+                        world.last_run = entry.last_run;
+                        entry.last_run = world.change_tick();
+                        run_batch(world, &mut [entry], None);
+                        world.change_tick += 1;
+                    }
+                }
+            }
+            for &index in stage.iter().flatten() {
+                self.systems[index].commands.apply(world);
+            }
+            world.change_tick += 1;
+        }
+        world.last_run = start_tick - 1;
+    }
+
+    /* Sorts each stage so every before/after constraint holds, ties keep the order systems were added in.
+     * The sorted stage is then cut into batches of neighbouring systems that neither conflict nor are ordered
+     * against each other, so running a batch at once gives the same result as running it in sorted order.
+     */
+    fn build_order(&self) -> Result<Vec<Vec<Batch>>, String> {
+        let index_of = |label: &str| {
+            self.systems
+                .iter()
+                .position(|entry| entry.label == label)
+                .ok_or(format!("no system is labelled {}", label))
+        };
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+        for (index, entry) in self.systems.iter().enumerate() {
+            for label in &entry.before {
+                edges.push((index, index_of(label)?));
+            }
+            for label in &entry.after {
+                edges.push((index_of(label)?, index));
+            }
+        }
+
+        let mut order = Vec::new();
+        for stage in Stage::ALL {
+            let mut remaining: Vec<usize> = (0..self.systems.len()).filter(|&i| self.systems[i].stage == stage).collect();
+            let mut stage_order = Vec::new();
+            while !remaining.is_empty() {
+                let ready = remaining
+                    .iter()
+                    .position(|&i| !edges.iter().any(|&(from, to)| to == i && remaining.contains(&from)))
+                    .ok_or(format!("systems in stage {:?} have cyclic ordering", stage))?;
+                stage_order.push(remaining.remove(ready));
+            }
+            let mut batches: Vec<Batch> = Vec::new();
+            for index in stage_order {
+                let joins_last = batches.last().is_some_and(|batch| {
+                    batch.iter().all(|&other| {
+                        !self.systems[index].conflicts(&self.systems[other]) && !edges.contains(&(other, index)) && !edges.contains(&(index, other))
+                    })
+                });
+                match batches.last_mut() {
+                    Some(batch) if joins_last => batch.push(index),
+                    _ => batches.push(vec![index]),
+                }
+            }
+            order.push(batches);
+        }
+
+        for &(from, to) in &edges {
+            if self.systems[from].stage > self.systems[to].stage {
+                return Err(format!(
+                    "{} must run before {} but is in a later stage",
+                    self.systems[from].label, self.systems[to].label
+                ));
+            }
+        }
+        Ok(order)
+    }
+}
+
+/* Systems that do not conflict can still match the same archetypes, and a query with a &mut element
+ * write-borrows every archetype it matches, so running them at once would panic on the borrow.
+ * Splits the batch into groups where no system writing anything shares an archetype with another.
+ * The batch does not conflict, so running the groups one after the other gives the same result.
+ */
+fn split_by_archetype<'s>(world: &World, entries: Vec<&'s mut SystemEntry>) -> Vec<Vec<&'s mut SystemEntry>> {
+    if entries.len() < 2 {
+        return vec![entries];
+    }
+    // every group keeps whether each of its systems writes and the archetypes it touches
+    let mut groups: Vec<(Vec<&mut SystemEntry>, Vec<Touched>)> = Vec::new();
+    for entry in entries {
+        let access = entry.access.as_ref().expect("systems without declared access always run alone");
+        let touched = (!access.writes.is_empty(), access.archetypes(world));
+        let fits = |members: &[Touched]| {
+            members.iter().all(|(writes, archetypes)| !(*writes || touched.0) || archetypes.is_disjoint(&touched.1))
+        };
+        match groups.iter_mut().find(|(_, members)| fits(members)) {
+            Some((group, members)) => {
+                group.push(entry);
+                members.push(touched);
+            }
+            None => groups.push((vec![entry], vec![touched])),
+        }
+    }
+    groups.into_iter().map(|(group, _)| group).collect()
+}
+
+type Touched = (bool, HashSet<ArchetypeId>);
+
+fn run_system(world: &World, entry: &mut SystemEntry) {
+    let mut commands = Commands::new(world, &mut entry.commands);
+    entry.system.run(world, &mut commands);
+}
+
+/* Shares the batch out over the worker threads, the calling thread taking the first share. */
+fn run_batch(world: &World, entries: &mut [&mut SystemEntry], workers: Option<&WorkerPool>) {
+    let Some(workers) = workers.filter(|_| entries.len() > 1) else {
+        for entry in entries {
+            run_system(world, entry);
+        }
+        return;
+    };
+    let share = entries.len().div_ceil(workers.senders.len() + 1);
+    let mut shares = entries.chunks_mut(share);
+    let local = shares.next().unwrap_or_default();
+    let mut pending = Pending { workers, count: 0 };
+    for (share, sender) in shares.zip(&workers.senders) {
+        let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+            for entry in share {
+                run_system(world, entry);
+            }
+        });
+        // the job borrows from this call, which cannot return or unwind before pending has seen it finish
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + '_>, Job>(job) };
+        sender.send(job).expect("schedule worker thread stopped");
+        pending.count += 1;
+    }
+    for entry in local {
+        run_system(world, entry);
+    }
+    pending.wait();
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/* Threads besides the one running the schedule, kept alive for as long as the schedule. */
+struct WorkerPool {
+    senders: Vec<Sender<Job>>,
+    finished: Receiver<thread::Result<()>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    fn new(threads: usize) -> Self {
+        let (finished_sender, finished) = mpsc::channel();
+        let mut senders = Vec::new();
+        let mut handles = Vec::new();
+        for index in 1..threads {
+            let (sender, jobs) = mpsc::channel::<Job>();
+            let finished = finished_sender.clone();
+            let handle = thread::Builder::new()
+                .name(format!("schedule worker {}", index))
+                .spawn(move || {
+                    for job in jobs {
+                        // a panicking system is reported back to the schedule, the worker keeps going
+                        if finished.send(panic::catch_unwind(AssertUnwindSafe(job))).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .expect("could not start a schedule worker thread");
+            senders.push(sender);
+            handles.push(handle);
+        }
+        WorkerPool { senders, finished, handles }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.senders.clear();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/* Jobs sent off by run_batch that have not finished yet. Waits for them when dropped, even while unwinding. */
+struct Pending<'p> {
+    workers: &'p WorkerPool,
+    count: usize,
+}
+
+impl Pending<'_> {
+    /* Rethrows the first panic of a job once all of them are done. */
+    fn wait(&mut self) {
+        let mut panic = None;
+        while self.count > 0 {
+            let result = self.workers.finished.recv().expect("schedule worker thread stopped");
+            self.count -= 1;
+            if let Err(payload) = result {
+                panic.get_or_insert(payload);
+            }
+        }
+        if let Some(payload) = panic {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        while self.count > 0 && self.workers.finished.recv().is_ok() {
+            self.count -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::entities::{commands::Commands, World};
+
+    use super::{Schedule, Stage};
+
+    struct P(u32);
+    struct V(u32);
+
+    /* Keeps the most systems seen running at once. */
+    #[derive(Clone, Default)]
+    struct Overlap {
+        running: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>,
+    }
+
+    impl Overlap {
+        fn busy(&self) {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+        }
+        fn most(&self) -> usize {
+            self.most.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn batches_run_on_worker_threads() {
+        let mut world = World::init();
+        let mut schedule = Schedule::with_threads(4);
+        let overlap = Overlap::default();
+        let threads: Arc<Mutex<HashSet<thread::ThreadId>>> = Arc::default();
+        for label in ["a", "b", "c", "d"] {
+            let (overlap, threads) = (overlap.clone(), threads.clone());
+            schedule
+                .add_system(Stage::Update, label, move |_: &World, _: &mut Commands| {
+                    threads.lock().unwrap().insert(thread::current().id());
+                    overlap.busy();
+                })
+                .no_access();
+        }
+        for _ in 0..5 {
+            world.run_schedule(&mut schedule);
+        }
+        assert_eq!(threads.lock().unwrap().len(), 4);
+        assert!(overlap.most() > 1);
+    }
+
+    /* Two systems with a query each, held while busy. */
+    fn writer_and_reader(overlap: &Overlap) -> Schedule {
+        let mut schedule = Schedule::with_threads(4);
+        let writer = overlap.clone();
+        schedule
+            .add_system(Stage::Update, "write_p", move |world: &World, _: &mut Commands| {
+                let mut query = world.query::<(&mut P,)>();
+                for (_, mut p) in query.iter_mut() {
+                    p.0 += 1;
+                }
+                writer.busy();
+            })
+            .writes::<P>();
+        let reader = overlap.clone();
+        schedule
+            .add_system(Stage::Update, "read_v", move |world: &World, _: &mut Commands| {
+                let query = world.query::<(&V,)>();
+                assert!(query.iter().all(|(_, v)| v.0 == 0));
+                reader.busy();
+            })
+            .reads::<V>();
+        schedule
+    }
+
+    #[test]
+    fn writers_never_share_an_archetype_with_a_parallel_system() {
+        let mut world = World::init();
+        for _ in 0..10 {
+            world.spawn((P(0), V(0)));
+        }
+        let overlap = Overlap::default();
+        let mut schedule = writer_and_reader(&overlap);
+        for _ in 0..5 {
+            world.run_schedule(&mut schedule);
+        }
+        assert_eq!(overlap.most(), 1);
+
+        // with the components in different archetypes the two can run at once again
+        let mut world = World::init();
+        for _ in 0..10 {
+            world.spawn((P(0),));
+            world.spawn((V(0),));
+        }
+        let overlap = Overlap::default();
+        let mut schedule = writer_and_reader(&overlap);
+        for _ in 0..5 {
+            world.run_schedule(&mut schedule);
+        }
+        assert_eq!(overlap.most(), 2);
+    }
+
+    #[test]
+    fn panics_on_workers_reach_the_caller() {
+        let mut world = World::init();
+        let mut schedule = Schedule::with_threads(4);
+        let finished = Arc::new(AtomicUsize::new(0));
+        for label in ["a", "b", "c"] {
+            let finished = finished.clone();
+            schedule
+                .add_system(Stage::Update, label, move |_: &World, _: &mut Commands| {
+                    thread::sleep(Duration::from_millis(20));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                })
+                .no_access();
+        }
+        schedule.add_system(Stage::Update, "boom", |_: &World, _: &mut Commands| panic!("boom")).no_access();
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| world.run_schedule(&mut schedule))).is_err());
+        // every other system had finished by the time the panic came through
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,95 @@
+use std::marker::PhantomData;
+
+use crate::entities::{components::ComponentTypeId, ArchetypeId, World};
+
+use super::{filter::QueryFilter, IntoQuery, Query};
+
+/* The archetypes a query matches, kept between runs so a system does not rescan the World every tick.
+ * Archetypes are never destroyed and their ids are handed out in creation order, so refreshing only has
+ * to look at the ones created since the last refresh. A state must only ever be used with one World.
+ */
+pub struct QueryState<Q: IntoQuery, F: QueryFilter = ()> {
+    components: Vec<ComponentTypeId>,
+    required: Vec<ComponentTypeId>,
+    excluded: Vec<ComponentTypeId>,
+    /* The required and excluded components that are stored in sparse sets, checked per row instead. */
+    sparse: Vec<ComponentTypeId>,
+    /* Number of sparse sets the World had when the components were last split up. */
+    sparse_generation: Option<usize>,
+    matched: Vec<ArchetypeId>,
+    /* Every archetype below this id has been checked. */
+    archetype_generation: ArchetypeId,
+    marker: PhantomData<fn() -> (Q, F)>,
+}
+
+impl<Q: IntoQuery, F: QueryFilter> Default for QueryState<Q, F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q: IntoQuery, F: QueryFilter> QueryState<Q, F> {
+    /* Matches nothing until the first update, which is done by query. */
+    pub fn new() -> Self {
+        let mut components = Vec::new();
+        let mut required = Vec::new();
+        let mut excluded = Vec::new();
+        Q::access(&mut components, &mut required);
+        F::access(&mut required, &mut excluded);
+        for (i, component) in components.iter().enumerate() {
+            assert!(!components[..i].contains(component), "{} is accessed more than once in the same query", component);
+        }
+        QueryState {
+            components,
+            required,
+            excluded,
+            sparse: Vec::new(),
+            sparse_generation: None,
+            matched: Vec::new(),
+            archetype_generation: 0,
+            marker: PhantomData,
+        }
+    }
+
+    /* Checks the archetypes created since the last update. Registering a sparse component starts over. */
+    pub fn update(&mut self, world: &World) {
+        if self.sparse_generation != Some(world.sparse_sets.len()) {
+            for component in &self.components {
+                assert!(!world.sparse_sets.contains_key(component), "{} is stored in a sparse set and can only be used in query filters", component);
+            }
+            self.sparse = self.required.iter().chain(self.excluded.iter()).copied().filter(|component| world.sparse_sets.contains_key(component)).collect();
+            self.sparse.sort();
+            self.sparse.dedup();
+            self.sparse_generation = Some(world.sparse_sets.len());
+            self.matched.clear();
+            self.archetype_generation = 0;
+        }
+        let generation = world.archetype_generation();
+        for archetype_id in self.archetype_generation..generation {
+            if self.matches(world, archetype_id) {
+                self.matched.push(archetype_id);
+            }
+        }
+        self.archetype_generation = generation;
+    }
+
+    /* Goes through World::archetype_sets rather than the archetype itself, so nothing gets borrowed. */
+    fn matches(&self, world: &World, archetype_id: ArchetypeId) -> bool {
+        let has = |component: &ComponentTypeId| world.archetype_sets.get(component).is_some_and(|set| set.contains(&archetype_id));
+        let dense = |component: &&ComponentTypeId| !self.sparse.contains(component);
+        self.required.iter().filter(dense).all(has) && !self.excluded.iter().filter(dense).any(has)
+    }
+
+    pub fn matched(&self) -> &[ArchetypeId] {
+        &self.matched
+    }
+
+    /* Updates the state and borrows the matched archetypes, with the World's last_run for Added and Changed. */
+    pub fn query<'w>(&mut self, world: &'w World) -> Query<'w, Q, F> {
+        self.query_since(world, world.last_run())
+    }
+    pub fn query_since<'w>(&mut self, world: &'w World, last_run: u32) -> Query<'w, Q, F> {
+        self.update(world);
+        Query::borrow(world, &self.matched, &self.sparse, last_run)
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    entities::{
+        commands::Commands,
+        components::{
+            collider::{Collider, Contacts},
+            motion::Velocity,
+            position::Position,
+        },
+        systems::{state::QueryState, System},
+        World,
+    },
+    map::{tile::TILE_SIZE, Map},
+    maths::vector::Vector,
+};
+
+use super::movement::Timestep;
+
+/* Slack for boxes that end up a rounding error past the tile edge they were pushed back to. */
+const EPSILON: f32 = 0.001;
+
+/* Stops every entity with a Collider at the solid tiles of the Map, meant to run right after apply_motion.
+ * The step apply_motion just took is found again from the Velocity and swept from where the entity started,
+ * the x axis first and then the y axis, so an entity running into a wall at an angle slides along it.
+ * Every tile the box passed over is checked, however fast it goes, and the velocity along a blocked axis is zeroed.
+ * The sides that got blocked are written to Contacts, which is added to entities that do not have it yet.
+ * Without a Map resource nothing is blocked.
+ */
+pub fn collide_with_tiles() -> impl System {
+    let mut colliders = QueryState::<(&mut Position, &mut Velocity, &Collider, Option<&mut Contacts>)>::new();
+    move |world: &World, commands: &mut Commands| collide(world, commands, &mut colliders)
+}
+
+fn collide(world: &World, commands: &mut Commands, colliders: &mut QueryState<(&mut Position, &mut Velocity, &Collider, Option<&mut Contacts>)>) {
+    let dt = world.resource::<Timestep>().expect("collide_with_tiles needs a Timestep resource").0;
+    let map = world.resource::<Map>();
+    let mut colliders = colliders.query(world);
+    for (id, mut position, mut velocity, collider, contacts) in colliders.iter_mut() {
+        let step = (velocity.0.x * dt, velocity.0.y * dt);
+        let start = position.0 - Vector::new(step.0, step.1, 0.0);
+        let mut delta = step;
+        let mut touched = Contacts::default();
+        if let Some(map) = &map {
+            let (min, max) = collider.bounds(start);
+            if let Some(clamped) = sweep(map, (min.x, max.x), (min.y, max.y), delta.0, true) {
+                touched.right = delta.0 > 0.0;
+                touched.left = delta.0 < 0.0;
+                delta.0 = clamped;
+            }
+            if let Some(clamped) = sweep(map, (min.y, max.y), (min.x + delta.0, max.x + delta.0), delta.1, false) {
+                touched.bottom = delta.1 > 0.0;
+                touched.top = delta.1 < 0.0;
+                delta.1 = clamped;
+            }
+        }
+        if (touched.left || touched.right) && velocity.0.x != 0.0 {
+            velocity.0.x = 0.0;
+        }
+        if (touched.top || touched.bottom) && velocity.0.y != 0.0 {
+            velocity.0.y = 0.0;
+        }
+        if delta != step {
+            position.0 = start + Vector::new(delta.0, delta.1, 0.0);
+        }
+        match contacts {
+            Some(mut contacts) => {
+                if *contacts != touched {
+                    *contacts = touched;
+                }
+            }
+            None => {
+                if touched.any() {
+                    commands.insert(id, touched);
+                }
+            }
+        }
+    }
+}
+
+/* Moves the box spanning along and across by delta along one axis, x when horizontal, and returns how far it gets
+ * if a solid tile is in the way. Checks every tile line from the edge of the box up to where the edge ends up.
+ */
+fn sweep(map: &Map, along: (f32, f32), across: (f32, f32), delta: f32, horizontal: bool) -> Option<f32> {
+    let tile = TILE_SIZE as f32;
+    let first_row = ((across.0 + EPSILON) / tile).floor() as i32;
+    let last_row = ((across.1 - EPSILON) / tile).ceil() as i32 - 1;
+    let solid = |line: &i32| {
+        (first_row..=last_row).any(|row| if horizontal { map.is_solid(*line, row) } else { map.is_solid(row, *line) })
+    };
+    if delta > 0.0 {
+        let first = ((along.1 - EPSILON) / tile).ceil() as i32;
+        let last = ((along.1 + delta) / tile).ceil() as i32 - 1;
+        (first..=last).find(solid).map(|line| line as f32 * tile - along.1)
+    } else if delta < 0.0 {
+        let first = ((along.0 + EPSILON) / tile).floor() as i32 - 1;
+        let last = ((along.0 + delta) / tile).floor() as i32;
+        (last..=first).rev().find(solid).map(|line| (line + 1) as f32 * tile - along.0)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::render::WindowCanvas;
+
+    use crate::{
+        entities::{
+            components::{
+                collider::{Collider, Contacts},
+                motion::Velocity,
+                position::Position,
+            },
+            systems::{
+                movement::{apply_motion, Timestep},
+                schedule::{Schedule, Stage},
+            },
+            EntityId, World,
+        },
+        map::{
+            tile::{Tile, Tiles},
+            Map,
+        },
+        maths::vector::Vector,
+    };
+
+    use super::collide_with_tiles;
+
+    /* Stands in for the textured tiles, Map only needs to know what is solid. */
+    struct Plain {
+        solid: bool,
+        id: usize,
+    }
+
+    impl Tile for Plain {
+        fn is_solid(&self) -> bool {
+            self.solid
+        }
+        fn get_id(&self) -> usize {
+            self.id
+        }
+        fn render(&self, _canvas: &mut WindowCanvas, _x: u32, _y: u32, _map: &Map) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    /* A room of floor 16 to 144 pixels across and 16 to 80 down, walled all around. */
+    const ROOM: &str = "0 0 0 0 0 0 0 0 0 0
+0 1 1 1 1 1 1 1 1 0
+0 1 1 1 1 1 1 1 1 0
+0 1 1 1 1 1 1 1 1 0
+0 1 1 1 1 1 1 1 1 0
+0 0 0 0 0 0 0 0 0 0";
+
+    fn room() -> (World, Schedule) {
+        let tiles = Tiles {
+            tiles: vec![Box::new(Plain { solid: true, id: 0 }), Box::new(Plain { solid: false, id: 1 })],
+        };
+        let mut world = World::init();
+        world.insert_resource(Map::parse(ROOM, &tiles).unwrap());
+        world.insert_resource(Timestep(1.0 / 60.0));
+        let mut schedule = Schedule::new();
+        schedule.add_system(Stage::Physics, "apply_motion", apply_motion());
+        schedule.add_system(Stage::Physics, "collide_with_tiles", collide_with_tiles()).after("apply_motion");
+        (world, schedule)
+    }
+
+    fn body(world: &mut World, x: f32, y: f32, velocity: (f32, f32)) -> EntityId {
+        world.spawn((Position(Vector::new(x, y, 0.0)), Velocity(Vector::new(velocity.0, velocity.1, 0.0)), Collider::new(8.0, 8.0)))
+    }
+
+    fn position(world: &World, id: EntityId) -> (f32, f32) {
+        let position = world.get::<Position>(id).unwrap().unwrap().0;
+        (position.x, position.y)
+    }
+
+    fn velocity(world: &World, id: EntityId) -> (f32, f32) {
+        let velocity = world.get::<Velocity>(id).unwrap().unwrap().0;
+        (velocity.x, velocity.y)
+    }
+
+    fn contacts(world: &World, id: EntityId) -> Contacts {
+        world.clone_component::<Contacts>(id).unwrap().unwrap_or_default()
+    }
+
+    #[test]
+    fn fast_bodies_never_tunnel() {
+        let (mut world, mut schedule) = room();
+        // 1000 pixels a tick, far more than the whole room
+        let right = body(&mut world, 20.0, 20.0, (60000.0, 0.0));
+        let up = body(&mut world, 60.0, 60.0, (0.0, -60000.0));
+        world.run_schedule(&mut schedule);
+        assert_eq!(position(&world, right), (136.0, 20.0));
+        assert_eq!(position(&world, up), (60.0, 16.0));
+        assert_eq!(velocity(&world, right), (0.0, 0.0));
+        assert_eq!(velocity(&world, up), (0.0, 0.0));
+
+        world.get_mut::<Velocity>(right).unwrap().unwrap().0 = Vector::new(-60000.0, 60000.0, 0.0);
+        world.run_schedule(&mut schedule);
+        assert_eq!(position(&world, right), (16.0, 72.0));
+    }
+
+    #[test]
+    fn bodies_slide_along_walls() {
+        let (mut world, mut schedule) = room();
+        // 20 pixels right and 5 down a tick, with the right wall 16 pixels away
+        let sliding = body(&mut world, 120.0, 20.0, (1200.0, 300.0));
+        world.run_schedule(&mut schedule);
+        assert_eq!(position(&world, sliding), (136.0, 25.0));
+        assert_eq!(velocity(&world, sliding), (0.0, 300.0));
+        world.run_schedule(&mut schedule);
+        assert_eq!(position(&world, sliding), (136.0, 30.0));
+
+        // the same along the floor
+        let skidding = body(&mut world, 40.0, 70.0, (-600.0, 1200.0));
+        world.run_schedule(&mut schedule);
+        assert_eq!(position(&world, skidding), (30.0, 72.0));
+        assert_eq!(velocity(&world, skidding), (-600.0, 0.0));
+    }
+
+    #[test]
+    fn contacts_follow_the_blocked_sides() {
+        let (mut world, mut schedule) = room();
+        let falling = body(&mut world, 40.0, 60.0, (0.0, 1200.0));
+        let floating = body(&mut world, 40.0, 40.0, (60.0, 0.0));
+        world.run_schedule(&mut schedule);
+        assert_eq!(contacts(&world, falling), Contacts { bottom: true, ..Contacts::default() });
+        // entities that never touched anything do not get Contacts at all
+        assert!(!world.has::<Contacts>(floating).unwrap());
+
+        world.get_mut::<Velocity>(falling).unwrap().unwrap().0 = Vector::new(-6000.0, -6000.0, 0.0);
+        world.run_schedule(&mut schedule);
+        assert_eq!(contacts(&world, falling), Contacts { left: true, top: true, ..Contacts::default() });
+
+        world.get_mut::<Velocity>(falling).unwrap().unwrap().0 = Vector::new(60.0, 60.0, 0.0);
+        world.run_schedule(&mut schedule);
+        assert!(world.has::<Contacts>(falling).unwrap());
+        assert!(!contacts(&world, falling).any());
+    }
+
+    #[test]
+    fn colliders_move_without_tile_collision() {
+        let mut world = World::init();
+        world.insert_resource(Timestep(1.0 / 60.0));
+        let mut schedule = Schedule::new();
+        schedule.add_system(Stage::Physics, "apply_motion", apply_motion());
+        let id = body(&mut world, 0.0, 0.0, (60.0, -120.0));
+        world.run_schedule(&mut schedule);
+        assert_eq!(position(&world, id), (1.0, -2.0));
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use crate::{
+    entities::{
+        commands::Commands,
+        components::{
+            hierarchy::{Children, Parent},
+            transform::GlobalTransform,
+        },
+        systems::{state::QueryState, System},
+        EntityId, World,
+    },
+    maths::transform::Transform,
+};
+
+/* Writes the GlobalTransform of every entity with a Transform, a child's being its parent's global
+ * transform composed with its own. Entities missing a GlobalTransform get one once the stage ends.
+ * Only globals that actually moved are written, so Changed<GlobalTransform> stays meaningful.
+ */
+pub fn propagate_transforms() -> impl System {
+    let mut transforms = QueryState::<(&Transform, Option<&Children>, Option<&Parent>)>::new();
+    let mut current_globals = QueryState::<(&mut GlobalTransform,)>::new();
+    move |world: &World, commands: &mut Commands| propagate(world, commands, &mut transforms, &mut current_globals)
+}
+
+fn propagate(
+    world: &World,
+    commands: &mut Commands,
+    transforms: &mut QueryState<(&Transform, Option<&Children>, Option<&Parent>)>,
+    current_globals: &mut QueryState<(&mut GlobalTransform,)>,
+) {
+    let mut locals: HashMap<EntityId, (Transform, Vec<EntityId>)> = HashMap::new();
+    let mut parents = Vec::new();
+    for (id, transform, children, parent) in transforms.query(world).iter() {
+        locals.insert(id, (*transform, children.map(|children| children.0.clone()).unwrap_or_default()));
+        parents.push((id, parent.map(Parent::get)));
+    }
+
+    // Children of an entity without a Transform are treated as roots
+    let mut stack: Vec<(EntityId, Transform)> = parents
+        .into_iter()
+        .filter(|(_, parent)| !parent.is_some_and(|parent| locals.contains_key(&parent)))
+        .map(|(id, _)| (id, locals[&id].0))
+        .collect();
+    let mut globals = HashMap::with_capacity(locals.len());
+    while let Some((id, global)) = stack.pop() {
+        for child in &locals[&id].1 {
+            if let Some((local, _)) = locals.get(child) {
+                stack.push((*child, &global * local));
+            }
+        }
+        globals.insert(id, global);
+    }
+
+    for (id, mut current) in current_globals.query(world).iter_mut() {
+        if let Some(global) = globals.remove(&id) {
+            if current.0 != global {
+                current.0 = global;
+            }
+        }
+    }
+    for (id, global) in globals {
+        commands.insert(id, GlobalTransform(global));
+    }
+}
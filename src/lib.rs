@@ -0,0 +1,4 @@
+pub mod assets;
+pub mod entities;
+pub mod map;
+pub mod maths;
@@ -1,6 +1,19 @@
-use entities::World;
-use entities::components::position::Position;
-use map::tile::Tiles;
+use rust_game::assets;
+use rust_game::assets::prefab::{PrefabLoader, PrefabManager};
+use rust_game::entities::World;
+use rust_game::entities::components::hierarchy::{Children, Parent};
+use rust_game::entities::components::transform::GlobalTransform;
+use rust_game::entities::events::Events;
+use rust_game::entities::systems::collision::{add_collisions, detect_collisions, CollisionEnded, CollisionStarted, Touching};
+use rust_game::entities::systems::movement::{apply_motion, Timestep};
+use rust_game::entities::systems::tile_collision::collide_with_tiles;
+use rust_game::entities::systems::schedule::{Schedule, Stage};
+use rust_game::entities::systems::transform::propagate_transforms;
+use rust_game::entities::components::collider::{Collider, Contacts};
+use rust_game::entities::components::motion::{Acceleration, Friction, MaxSpeed, Velocity};
+use rust_game::entities::components::position::Position;
+use rust_game::map;
+use rust_game::map::tile::Tiles;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::rect::Rect;
@@ -8,14 +21,9 @@ use sdl2::render::WindowCanvas;
 use std::ops::Div;
 use std::time::{Duration, Instant};
 // use crate::map::tile::Tiles;
-use crate::maths::transform::Transform;
-use crate::maths::vector;
-use crate::maths::vector::Vector;
-
-mod assets;
-mod entities;
-mod map;
-mod maths;
+use rust_game::maths::transform::Transform;
+use rust_game::maths::vector;
+use rust_game::maths::vector::Vector;
 
 const FPS: u32 = 60;
 
@@ -26,9 +34,6 @@ fn render(canvas: &mut WindowCanvas) {
     canvas.present();
 }
 
-fn tick() {
-    // tick here
-}
 fn main() -> Result<(), String> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
@@ -64,11 +69,57 @@ fn main() -> Result<(), String> {
     let mut timer = 0;
 
     let tiles = Tiles::init(&texture_atlas_manager.load("tiles").unwrap());
-    let map = map::Map::new("assets/rooms/room.rm", &tiles)?;
-
     let mut world = World::init();
-    let entity = world.new_entity();
-    world.set_component(entity, Position(1.0, 4.0))?;
+    world.insert_resource(map::Map::new("assets/rooms/room.rm", &tiles)?);
+    world.insert_resource(Timestep::from_fps(FPS));
+    world.register_component::<Position>("position")?;
+    world.register_component::<Velocity>("velocity")?;
+    world.register_component::<Acceleration>("acceleration")?;
+    world.register_component::<Friction>("friction")?;
+    world.register_component::<MaxSpeed>("max_speed")?;
+    world.register_component::<Collider>("collider")?;
+    world.register_component::<Contacts>("contacts")?;
+    add_collisions(&mut world);
+    world.register_debug::<Transform>();
+    world.register_debug::<GlobalTransform>();
+    world.register_debug::<Parent>();
+    world.register_debug::<Children>();
+    world.insert_resource(PrefabManager::new(&PrefabLoader));
+    world.spawn_prefab("player")?;
+    world.spawn_prefab("zombie")?;
+    let mut schedule = Schedule::new();
+    schedule
+        .add_system(Stage::Physics, "apply_motion", apply_motion())
+        .writes::<Position>()
+        .writes::<Velocity>()
+        .reads::<Acceleration>()
+        .reads::<Friction>()
+        .reads::<MaxSpeed>()
+        .reads_resource::<Timestep>();
+    schedule
+        .add_system(Stage::Physics, "collide_with_tiles", collide_with_tiles())
+        .after("apply_motion")
+        .writes::<Position>()
+        .writes::<Velocity>()
+        .writes::<Contacts>()
+        .reads::<Collider>()
+        .reads_resource::<Timestep>()
+        .reads_resource::<map::Map>();
+    schedule
+        .add_system(Stage::Physics, "detect_collisions", detect_collisions())
+        .after("collide_with_tiles")
+        .reads::<Position>()
+        .reads::<Collider>()
+        .writes_resource::<Events<CollisionStarted>>()
+        .writes_resource::<Events<CollisionEnded>>()
+        .writes_resource::<Touching>();
+    schedule
+        .add_system(Stage::RenderPrep, "propagate_transforms", propagate_transforms())
+        .reads::<Transform>()
+        .reads::<Parent>()
+        .reads::<Children>()
+        .writes::<GlobalTransform>();
+    let mut inspector = None;
     'running: loop {
         let now = Instant::now();
         delta += (now - last_time).as_nanos() as f32 / time_per_tick.as_nanos() as f32;
@@ -85,6 +136,19 @@ fn main() -> Result<(), String> {
                 } => {
                     break 'running;
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => {
+                    inspector = match inspector {
+                        Some(_) => None,
+                        None => {
+                            let dump = world.inspect();
+                            println!("{}", dump);
+                            Some(dump)
+                        }
+                    };
+                }
                 Event::KeyDown {
                     keycode: Some(Keycode::KpPlus),
                     ..
@@ -130,19 +194,27 @@ fn main() -> Result<(), String> {
         }
         // Tick
         if delta >= 1.0 {
-            tick();
+            world.run_schedule(&mut schedule);
             ticks += 1;
             delta -= 1.0;
             // let camera_offset = camera.transform;
             // Render
             canvas.set_draw_color(sdl2::pixels::Color::RGB(100, 100, 100));
             canvas.clear();
-            map.render(&mut canvas).ok();
+            if let Some(map) = world.resource::<map::Map>() {
+                map.render(&mut canvas, &tiles).ok();
+            }
+            if let Some(dump) = &inspector {
+                dump.render_overlay(&mut canvas).ok();
+            }
             canvas.present();
             // render(&mut canvas);
         }
         if timer >= 1_000_000_000 {
             println!("Ticks and Frames: {}", ticks);
+            if inspector.is_some() {
+                inspector = Some(world.inspect());
+            }
             ticks = 0;
             timer = 0;
         }
@@ -1,60 +1,81 @@
-use crate::map::tile::{Tile, Tiles};
+use crate::map::tile::Tiles;
 use crate::maths::transform::Transform;
 use sdl2::render::WindowCanvas;
 use std::fs;
 use std::path::Path;
 
 pub mod tile;
-pub struct Map<'map> {
+pub struct Map {
     width: u32,
     height: u32,
-    tiles: Vec<Vec<&'map dyn Tile>>,
+    tiles: Vec<Vec<usize>>,
+    /* Tile::is_solid of every tile, looked up once so collision does not need the Tiles. */
+    solid: Vec<Vec<bool>>,
     entities: Vec<EntitySpawn>,
 }
-impl Map<'_> {
-    pub fn new<P>(path: P, tiles: &Tiles) -> Result<Map<'_>, String>
+impl Map {
+    pub fn new<P>(path: P, tiles: &Tiles) -> Result<Map, String>
     where
         P: AsRef<Path>,
     {
         if let Ok(map_string) = fs::read_to_string(path) {
-            let map_string = map_string;
-            let mut map_tiles = Vec::new();
-            for line in map_string.lines() {
-                let mut row = Vec::new();
-                for tile in line.split(' ') {
-                    let tile_id = tile.parse::<usize>().map_err(|e| e.to_string())?;
-                    let tile = tiles
-                        .tiles
-                        .get(tile_id)
-                        .expect("Tile id out of bounds")
-                        .as_ref();
-                    row.push(tile);
-                }
-                map_tiles.push(row);
-            }
-            let map = Map {
-                width: map_tiles[0].len() as u32,
-                height: map_tiles.len() as u32,
-                tiles: map_tiles,
-                entities: Vec::new(),
-            };
-            Ok(map)
+            Map::parse(&map_string, tiles)
         } else {
             Err("Could not read map file".to_string())
         }
     }
-    pub fn render(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+    /* One line per row of tiles, each tile an index into Tiles::tiles, separated by spaces. */
+    pub fn parse(map_string: &str, tiles: &Tiles) -> Result<Map, String> {
+        let mut map_tiles = Vec::new();
+        let mut solid = Vec::new();
+        for line in map_string.lines() {
+            let mut row = Vec::new();
+            let mut solid_row = Vec::new();
+            for tile in line.split(' ') {
+                let tile_id = tile.parse::<usize>().map_err(|e| e.to_string())?;
+                let tile = tiles
+                    .tiles
+                    .get(tile_id)
+                    .ok_or(format!("Tile id {} out of bounds", tile_id))?;
+                row.push(tile_id);
+                solid_row.push(tile.is_solid());
+            }
+            if map_tiles.first().is_some_and(|first: &Vec<usize>| first.len() != row.len()) {
+                return Err(format!("Row {} of the map is not as wide as the first", map_tiles.len()));
+            }
+            map_tiles.push(row);
+            solid.push(solid_row);
+        }
+        let width = map_tiles.first().ok_or("Map is empty")?.len() as u32;
+        let map = Map {
+            width,
+            height: map_tiles.len() as u32,
+            tiles: map_tiles,
+            solid,
+            entities: Vec::new(),
+        };
+        Ok(map)
+    }
+    pub fn render(&self, canvas: &mut WindowCanvas, tiles: &Tiles) -> Result<(), String> {
         for y in 0..self.height {
             for x in 0..self.width {
-                self.tiles[y as usize][x as usize].render(canvas, x, y, self)?;
+                tiles.tiles[self.tiles[y as usize][x as usize]].render(canvas, x, y, self)?;
             }
         }
         Ok(())
     }
 
-    pub fn get_tile(&self, x: usize, y: usize) -> Option<&dyn Tile> {
+    pub fn get_tile_id(&self, x: usize, y: usize) -> Option<usize> {
         self.tiles.get(y)?.get(x).copied()
     }
+
+    /* Takes tile coordinates. Everything outside the map counts as solid, so nothing can leave it. */
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 {
+            return true;
+        }
+        self.solid.get(y as usize).and_then(|row| row.get(x as usize)).copied().unwrap_or(true)
+    }
 }
 struct EntitySpawn {
     // entity: Entity,
@@ -28,12 +28,12 @@ impl Tiles {
         let mut tiles = Tiles { tiles: Vec::new() };
         tiles.tiles.push(Box::new(ConnectingTile::new(
             tiles_atlas.get_region("sheet_wall").unwrap().unwrap_atlas(),
-            false,
+            true,
             0,
         )));
         tiles.tiles.push(Box::new(BasicTile::new(
             tiles_atlas.get_region("floor").unwrap().unwrap_single(),
-            true,
+            false,
             1,
         )));
         tiles
@@ -120,11 +120,11 @@ impl Tile for ConnectingTile {
                     continue;
                 }
                 let same: bool;
-                if let Some(neighbour) = map.get_tile(
+                if let Some(neighbour) = map.get_tile_id(
                     (x_offset + x as i32) as usize,
                     (y_offset + y as i32) as usize,
                 ) {
-                    same = neighbour.get_id() == self.id;
+                    same = neighbour == self.id;
                 } else {
                     same = false;
                 }
@@ -2,7 +2,7 @@ use std::ops::{Div, Mul};
 
 use crate::maths::vector::Vector;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Transform {
     pub pos: Vector,
     pub rot: f32,
@@ -103,7 +103,7 @@ impl Vector {
     pub(crate) fn new(x: f32, y: f32, z: f32) -> Vector {
         Vector { x, y, z }
     }
-    fn mag_2d(&self) -> f32 {
+    pub(crate) fn mag_2d(&self) -> f32 {
         (self.x * self.x + self.y * self.y).sqrt()
     }
     fn mag_2d2(&self) -> f32 {